@@ -1,14 +1,33 @@
 mod lib {
+    pub mod cache;
+    pub mod config;
     pub mod events;
+    pub mod front_matter;
+    pub mod hash;
     pub mod manager;
+    #[cfg(test)]
+    pub mod mock_server;
     pub mod process;
     pub mod openai;
+    pub mod pause;
+    pub mod provider;
+    pub mod time;
+    pub mod tokens;
+    pub mod usage;
 }
 
 pub mod processors;
 
+pub use lib::cache::{CachedProvider, ResponseCache, CACHE_DIR, DRY_RUN_ENV};
+pub use lib::config::{DirConfig, CONFIG_FILE};
 pub use lib::events::{FileEvent, EventKind, EventOrigin};
-pub use lib::manager::Manager;
+pub use lib::manager::{Manager, ProgressWriter, WatcherBackend};
 pub use lib::process::SyncProcess;
-pub use lib::openai::OpenAIClient;
+pub use lib::openai::{OpenAIClient, DEFAULT_CONNECT_TIMEOUT, DEFAULT_EMBEDDING_MODEL, DEFAULT_MAX_RETRIES, DEFAULT_READ_TIMEOUT, OPENAI_BASE_URL};
+pub use lib::provider::{Completion, CompletionOptions, EmbeddingProvider, LlmMessage, LlmProvider, Role, ToolCall, ToolSpec, Usage};
+pub use lib::usage::{format_cost, UsageStore, USAGE_FILE};
+pub use lib::front_matter::FrontMatter;
+pub use lib::time::{format_timestamp, now_timestamp, unix_now};
+pub use lib::tokens::{estimate_messages, estimate_tokens};
+pub use lib::pause::{PauseState, PAUSE_SENTINEL};
 pub use processors::{create_sync_a_to_b, create_sync_a_to_c, create_chat_processor};
@@ -0,0 +1,390 @@
+use super::config::DirConfig;
+use super::hash::fnv1a;
+use super::provider::{Completion, CompletionOptions, LlmMessage, LlmProvider, ToolSpec, Usage};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use super::time::unix_now;
+use std::time::{Duration, SystemTime};
+
+/// Cache directory if .mara.toml doesn't set one, relative to the watch root
+pub const CACHE_DIR: &str = ".mara-cache";
+
+/// Environment variable for dry runs during development, turns the cache on by default
+pub const DRY_RUN_ENV: &str = "MARA_DRY_RUN";
+
+const DEFAULT_TTL: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+const DEFAULT_MAX_BYTES: u64 = 50 * 1024 * 1024;
+
+#[derive(Debug, Serialize, Deserialize)]
+struct CacheEntry {
+    created: u64,
+    completion: Completion,
+}
+
+/// ResponseCache struct - completions on disk, one JSON file per request
+/// Entries older than `ttl` are ignored, the oldest entries are removed above `max_bytes`
+#[derive(Debug, Clone, PartialEq)]
+pub struct ResponseCache {
+    dir: PathBuf,
+    ttl: Duration,
+    max_bytes: u64,
+}
+
+impl ResponseCache {
+    pub fn new(dir: &Path) -> Self {
+        ResponseCache {
+            dir: dir.to_path_buf(),
+            ttl: DEFAULT_TTL,
+            max_bytes: DEFAULT_MAX_BYTES,
+        }
+    }
+
+    pub fn with_ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    pub fn with_max_bytes(mut self, max_bytes: u64) -> Self {
+        self.max_bytes = max_bytes;
+        self
+    }
+
+    /// Whether the cache is used if .mara.toml doesn't say: in tests and dry runs
+    pub fn enabled_by_default() -> bool {
+        cfg!(test) || std::env::var(DRY_RUN_ENV).map(|v| v != "0" && !v.is_empty()).unwrap_or(false)
+    }
+
+    /// Read the cache settings from .mara.toml, None if the cache is off
+    /// [cache]
+    /// enabled = true
+    /// dir = ".mara-cache"     # relative to the watch root
+    /// ttl_secs = 604800
+    /// max_bytes = 52428800
+    pub fn from_config(config: &DirConfig, root: &Path) -> Option<Self> {
+        let enabled = config
+            .get_bool("cache", "enabled")
+            .unwrap_or_else(Self::enabled_by_default);
+        if !enabled {
+            return None;
+        }
+
+        let dir = root.join(config.get_str("cache", "dir").unwrap_or(CACHE_DIR));
+        let mut cache = ResponseCache::new(&dir);
+        if let Some(ttl) = config.get_int("cache", "ttl_secs") {
+            cache = cache.with_ttl(Duration::from_secs(ttl.max(0) as u64));
+        }
+        if let Some(max_bytes) = config.get_int("cache", "max_bytes") {
+            cache = cache.with_max_bytes(max_bytes.max(0) as u64);
+        }
+        Some(cache)
+    }
+
+    /// Key of a request: server, model, options, messages and tools
+    /// The request is hashed as JSON with FNV-1a, keys stay the same across builds
+    pub fn key(
+        base_url: &str,
+        model: &str,
+        options: &CompletionOptions,
+        messages: &[LlmMessage],
+        tools: &[ToolSpec],
+    ) -> String {
+        let messages: Vec<_> = messages
+            .iter()
+            .map(|message| {
+                json!({
+                    "role": message.role.as_str(),
+                    "name": message.name,
+                    "content": message.content,
+                    "tool_calls": message.tool_calls,
+                    "tool_call_id": message.tool_call_id,
+                })
+            })
+            .collect();
+        let tools: Vec<_> = tools
+            .iter()
+            .map(|tool| json!({"name": tool.name, "description": tool.description, "parameters": tool.parameters}))
+            .collect();
+        let request = json!({
+            "base_url": base_url.trim_end_matches('/'),
+            "model": model,
+            "system": options.system,
+            "temperature": options.temperature,
+            "max_tokens": options.max_tokens,
+            "messages": messages,
+            "tools": tools,
+        });
+        format!("{:016x}", fnv1a(request.to_string().as_bytes()))
+    }
+
+    fn path(&self, key: &str) -> PathBuf {
+        self.dir.join(format!("{}.json", key))
+    }
+
+    /// Cached completion of a request, expired entries are removed
+    pub fn get(&self, key: &str) -> Option<Completion> {
+        let path = self.path(key);
+        let content = fs::read_to_string(&path).ok()?;
+        let entry: CacheEntry = serde_json::from_str(&content).ok()?;
+
+        if unix_now().saturating_sub(entry.created) >= self.ttl.as_secs() {
+            let _ = fs::remove_file(&path);
+            return None;
+        }
+        Some(entry.completion)
+    }
+
+    /// Store a completion, then shrink the cache below `max_bytes`
+    pub fn put(&self, key: &str, completion: &Completion) -> Result<(), String> {
+        fs::create_dir_all(&self.dir)
+            .map_err(|e| format!("Failed to create cache dir {}: {}", self.dir.display(), e))?;
+
+        let entry = CacheEntry {
+            created: unix_now(),
+            completion: completion.clone(),
+        };
+        let content = serde_json::to_string(&entry).map_err(|e| e.to_string())?;
+        fs::write(self.path(key), content).map_err(|e| format!("Failed to write cache entry: {}", e))?;
+
+        self.evict();
+        Ok(())
+    }
+
+    /// Remove the oldest entries until the cache fits into `max_bytes`
+    fn evict(&self) {
+        let Ok(entries) = fs::read_dir(&self.dir) else {
+            return;
+        };
+
+        let mut files: Vec<(SystemTime, u64, PathBuf)> = entries
+            .flatten()
+            .filter(|entry| entry.path().extension().and_then(|e| e.to_str()) == Some("json"))
+            .filter_map(|entry| {
+                let metadata = entry.metadata().ok()?;
+                Some((metadata.modified().ok()?, metadata.len(), entry.path()))
+            })
+            .collect();
+        files.sort();
+
+        let mut total: u64 = files.iter().map(|(_, len, _)| len).sum();
+        for (_, len, path) in files {
+            if total <= self.max_bytes {
+                break;
+            }
+            if fs::remove_file(&path).is_ok() {
+                total -= len;
+            }
+        }
+    }
+}
+
+/// CachedProvider struct - answers repeated requests from a ResponseCache
+/// Only successful completions are stored, errors always reach the provider again
+pub struct CachedProvider<P> {
+    inner: P,
+    cache: ResponseCache,
+    base_url: String,
+}
+
+impl<P: LlmProvider> CachedProvider<P> {
+    pub fn new(inner: P, cache: ResponseCache) -> Self {
+        CachedProvider { inner, cache, base_url: String::new() }
+    }
+
+    /// Server of the provider, the same model on another server gets other entries
+    pub fn with_base_url(mut self, base_url: &str) -> Self {
+        self.base_url = base_url.to_string();
+        self
+    }
+
+    fn key(&self, options: &CompletionOptions, messages: &[LlmMessage], tools: &[ToolSpec]) -> String {
+        let model = options.model.as_deref().unwrap_or(self.inner.model());
+        ResponseCache::key(&self.base_url, model, options, messages, tools)
+    }
+
+    fn store(&self, key: &str, completion: &Completion) {
+        if let Err(e) = self.cache.put(key, completion) {
+            eprintln!("Cache error: {}", e);
+        }
+    }
+}
+
+impl<P: LlmProvider> LlmProvider for CachedProvider<P> {
+    fn model(&self) -> &str {
+        self.inner.model()
+    }
+
+    /// Only requests that reached the provider, cache hits are free
+    fn usage(&self) -> BTreeMap<String, Usage> {
+        self.inner.usage()
+    }
+
+    async fn generate_response(
+        &self,
+        messages: Vec<LlmMessage>,
+        options: CompletionOptions,
+    ) -> Result<String, String> {
+        let key = self.key(&options, &messages, &[]);
+        if let Some(completion) = self.cache.get(&key) {
+            return Ok(completion.content);
+        }
+
+        let content = self.inner.generate_response(messages, options).await?;
+        self.store(&key, &Completion { content: content.clone(), tool_calls: Vec::new() });
+        Ok(content)
+    }
+
+    async fn generate_with_tools(
+        &self,
+        messages: Vec<LlmMessage>,
+        options: CompletionOptions,
+        tools: Vec<ToolSpec>,
+    ) -> Result<Completion, String> {
+        let key = self.key(&options, &messages, &tools);
+        if let Some(completion) = self.cache.get(&key) {
+            return Ok(completion);
+        }
+
+        let completion = self.inner.generate_with_tools(messages, options, tools).await?;
+        self.store(&key, &completion);
+        Ok(completion)
+    }
+
+    async fn generate_response_stream<F: FnMut(&str) + Send>(
+        &self,
+        messages: Vec<LlmMessage>,
+        options: CompletionOptions,
+        mut on_chunk: F,
+    ) -> Result<String, String> {
+        // Same key as generate_response, a cached reply arrives as a single chunk
+        let key = self.key(&options, &messages, &[]);
+        if let Some(completion) = self.cache.get(&key) {
+            on_chunk(&completion.content);
+            return Ok(completion.content);
+        }
+
+        let content = self.inner.generate_response_stream(messages, options, on_chunk).await?;
+        self.store(&key, &Completion { content: content.clone(), tool_calls: Vec::new() });
+        Ok(content)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Role;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    const OPENAI: &str = "https://api.openai.com/v1";
+
+    fn cache_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("mara_cache_{}_{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn completion(content: &str) -> Completion {
+        Completion { content: content.to_string(), tool_calls: Vec::new() }
+    }
+
+    struct CountingProvider {
+        calls: AtomicUsize,
+    }
+
+    impl LlmProvider for CountingProvider {
+        fn model(&self) -> &str {
+            "counting"
+        }
+
+        async fn generate_response(&self, messages: Vec<LlmMessage>, _options: CompletionOptions) -> Result<String, String> {
+            self.calls.fetch_add(1, Ordering::SeqCst);
+            Ok(format!("Antwort auf {}", messages[0].content))
+        }
+    }
+
+    #[test]
+    fn test_key() {
+        let options = CompletionOptions::default();
+        let messages = vec![LlmMessage::new(Role::User, "hallo")];
+        let key = ResponseCache::key(OPENAI, "gpt-4", &options, &messages, &[]);
+        assert_eq!(key, ResponseCache::key(OPENAI, "gpt-4", &options, &messages, &[]));
+        assert_ne!(key, ResponseCache::key(OPENAI, "llama", &options, &messages, &[]));
+        assert_ne!(key, ResponseCache::key("http://localhost:8080/v1", "gpt-4", &options, &messages, &[]));
+
+        let warmer = CompletionOptions { temperature: Some(0.9), ..CompletionOptions::default() };
+        assert_ne!(key, ResponseCache::key(OPENAI, "gpt-4", &warmer, &messages, &[]));
+    }
+
+    #[test]
+    fn test_key_is_stable() {
+        // Entries on disk must be found again by later builds
+        let messages = vec![LlmMessage::new(Role::User, "hallo")];
+        let key = ResponseCache::key(OPENAI, "gpt-4", &CompletionOptions::default(), &messages, &[]);
+        assert_eq!(key, "e62c8ce5c891258f");
+    }
+
+    #[test]
+    fn test_put_get() {
+        let dir = cache_dir("put_get");
+        let cache = ResponseCache::new(&dir);
+        assert_eq!(cache.get("abc"), None);
+        cache.put("abc", &completion("Hallo")).unwrap();
+        assert_eq!(cache.get("abc"), Some(completion("Hallo")));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_ttl() {
+        let dir = cache_dir("ttl");
+        let cache = ResponseCache::new(&dir).with_ttl(Duration::ZERO);
+        cache.put("abc", &completion("Hallo")).unwrap();
+        assert_eq!(cache.get("abc"), None);
+        assert!(!dir.join("abc.json").exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_size_cap() {
+        let dir = cache_dir("size");
+        let cache = ResponseCache::new(&dir).with_max_bytes(150);
+        cache.put("old", &completion(&"a".repeat(60))).unwrap();
+        std::thread::sleep(Duration::from_millis(20));
+        cache.put("new", &completion(&"b".repeat(60))).unwrap();
+        assert_eq!(cache.get("old"), None);
+        assert!(cache.get("new").is_some());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_cached_provider() {
+        let dir = cache_dir("provider");
+        let provider = CachedProvider::new(CountingProvider { calls: AtomicUsize::new(0) }, ResponseCache::new(&dir));
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        let messages = vec![LlmMessage::new(Role::User, "hallo")];
+
+        let first = rt.block_on(provider.generate_response(messages.clone(), CompletionOptions::default()));
+        let mut chunks = Vec::new();
+        let second = rt.block_on(provider.generate_response_stream(messages, CompletionOptions::default(), |c| {
+            chunks.push(c.to_string())
+        }));
+
+        assert_eq!(first, Ok("Antwort auf hallo".to_string()));
+        assert_eq!(second, first);
+        assert_eq!(chunks, vec!["Antwort auf hallo".to_string()]);
+        assert_eq!(provider.inner.calls.load(Ordering::SeqCst), 1);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_from_config() {
+        let root = Path::new("/tmp/root");
+        let config = DirConfig::parse("[cache]\nenabled = false").unwrap();
+        assert_eq!(ResponseCache::from_config(&config, root), None);
+
+        let config = DirConfig::parse("[cache]\ndir = \"cache\"\nttl_secs = 60").unwrap();
+        let cache = ResponseCache::from_config(&config, root).unwrap();
+        assert_eq!(cache, ResponseCache::new(&root.join("cache")).with_ttl(Duration::from_secs(60)));
+    }
+}
@@ -0,0 +1,156 @@
+use std::fs;
+use std::path::Path;
+use toml::{Table, Value};
+
+/// Name of the per-directory config file
+pub const CONFIG_FILE: &str = ".mara.toml";
+
+/// DirConfig struct - merged settings of all .mara.toml files from the watch root down to a directory
+///
+/// Format (sections are named after the processor they configure):
+/// [chat]
+/// model = "gpt-4o"
+///
+/// [command]
+/// enabled = false
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct DirConfig {
+    pub settings: Table,
+}
+
+impl DirConfig {
+    pub fn new() -> Self {
+        DirConfig {
+            settings: Table::new(),
+        }
+    }
+
+    /// Parse the content of a single .mara.toml file
+    pub fn parse(content: &str) -> Result<Self, String> {
+        let settings = content
+            .parse::<Table>()
+            .map_err(|e| format!("Invalid {}: {}", CONFIG_FILE, e))?;
+        Ok(DirConfig { settings })
+    }
+
+    /// Load the merged config for a directory
+    /// Every directory from the root down to `dir` may contain a .mara.toml,
+    /// settings of deeper directories override the ones inherited from above
+    pub fn load(root: Option<&Path>, dir: &Path) -> Self {
+        let mut config = DirConfig::new();
+
+        let dirs: Vec<&Path> = match root {
+            Some(root) if dir.starts_with(root) => {
+                let mut dirs: Vec<&Path> = dir
+                    .ancestors()
+                    .take_while(|d| d.starts_with(root))
+                    .collect();
+                dirs.reverse();
+                dirs
+            }
+            _ => vec![dir],
+        };
+
+        for dir in dirs {
+            let path = dir.join(CONFIG_FILE);
+            let Ok(content) = fs::read_to_string(&path) else {
+                continue;
+            };
+
+            match DirConfig::parse(&content) {
+                Ok(child) => config.merge(child),
+                Err(e) => println!("Config error in {}: {}", path.display(), e),
+            }
+        }
+
+        config
+    }
+
+    /// Merge another config into this one, values of `other` win
+    pub fn merge(&mut self, other: DirConfig) {
+        merge_tables(&mut self.settings, other.settings);
+    }
+
+    pub fn get(&self, section: &str, key: &str) -> Option<&Value> {
+        self.settings.get(section)?.as_table()?.get(key)
+    }
+
+    pub fn get_str(&self, section: &str, key: &str) -> Option<&str> {
+        self.get(section, key)?.as_str()
+    }
+
+    pub fn get_bool(&self, section: &str, key: &str) -> Option<bool> {
+        self.get(section, key)?.as_bool()
+    }
+
+    pub fn get_int(&self, section: &str, key: &str) -> Option<i64> {
+        self.get(section, key)?.as_integer()
+    }
+
+    /// Floats may also be written as integers (`temperature = 1`)
+    pub fn get_float(&self, section: &str, key: &str) -> Option<f64> {
+        match self.get(section, key)? {
+            Value::Float(f) => Some(*f),
+            Value::Integer(i) => Some(*i as f64),
+            _ => None,
+        }
+    }
+}
+
+fn merge_tables(base: &mut Table, other: Table) {
+    for (key, value) in other {
+        match (base.get_mut(&key), value) {
+            (Some(Value::Table(base_table)), Value::Table(other_table)) => {
+                merge_tables(base_table, other_table);
+            }
+            (_, value) => {
+                base.insert(key, value);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_sections() {
+        let config = DirConfig::parse("[chat]\nmodel = \"gpt-4o\"\ntemperature = 1\n").unwrap();
+        assert_eq!(config.get_str("chat", "model"), Some("gpt-4o"));
+        assert_eq!(config.get_float("chat", "temperature"), Some(1.0));
+        assert_eq!(config.get_str("command", "model"), None);
+    }
+
+    #[test]
+    fn test_parse_invalid() {
+        assert!(DirConfig::parse("[chat\nmodel = ").is_err());
+    }
+
+    #[test]
+    fn test_merge_overrides_keys() {
+        let mut config = DirConfig::parse("[chat]\nmodel = \"gpt-4\"\ntemperature = 0.5\n").unwrap();
+        config.merge(DirConfig::parse("[chat]\nmodel = \"gpt-4o\"\n[command]\nenabled = false\n").unwrap());
+        assert_eq!(config.get_str("chat", "model"), Some("gpt-4o"));
+        assert_eq!(config.get_float("chat", "temperature"), Some(0.5));
+        assert_eq!(config.get_bool("command", "enabled"), Some(false));
+    }
+
+    #[test]
+    fn test_load_inherits_down_the_tree() {
+        let root = std::env::temp_dir().join(format!("mara_config_test_{}", std::process::id()));
+        let sub = root.join("chats").join("work");
+        fs::create_dir_all(&sub).unwrap();
+        fs::write(root.join(CONFIG_FILE), "[chat]\nmodel = \"gpt-4\"\ntemperature = 0.2\n").unwrap();
+        fs::write(root.join("chats").join(CONFIG_FILE), "[chat]\nmodel = \"gpt-4o\"\n").unwrap();
+
+        let config = DirConfig::load(Some(&root), &sub);
+        assert_eq!(config.get_str("chat", "model"), Some("gpt-4o"));
+        assert_eq!(config.get_float("chat", "temperature"), Some(0.2));
+
+        let config = DirConfig::load(Some(&root), &root);
+        assert_eq!(config.get_str("chat", "model"), Some("gpt-4"));
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -1,10 +1,16 @@
-use std::path::PathBuf;
+use super::config::DirConfig;
+use super::manager::ProgressWriter;
+use std::path::{Path, PathBuf};
 
 #[derive(Debug, Clone)]
 pub struct FileEvent {
     pub path: PathBuf,
+    pub root: Option<PathBuf>,
+    pub relative_path: PathBuf,
     pub event_kind: EventKind,
     pub origin: EventOrigin,
+    pub config: DirConfig,
+    pub progress: Option<ProgressWriter>,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq)]
@@ -23,9 +29,13 @@ pub enum EventOrigin {
 impl FileEvent {
     pub fn new(path: PathBuf, event_kind: EventKind) -> Self {
         Self {
+            relative_path: path.clone(),
             path,
+            root: None,
             event_kind,
             origin: EventOrigin::External,
+            config: DirConfig::new(),
+            progress: None,
         }
     }
 
@@ -34,11 +44,72 @@ impl FileEvent {
         self
     }
 
+    /// Set the watch root the event belongs to, `relative_path` becomes relative to it
+    pub fn with_root(mut self, root: &Path) -> Self {
+        if let Ok(relative) = self.path.strip_prefix(root) {
+            self.relative_path = relative.to_path_buf();
+        }
+        self.root = Some(root.to_path_buf());
+        self
+    }
+
+    /// Resolve a target path of a process
+    /// Relative targets are resolved against the watch root, not the current working directory
+    pub fn resolve(&self, target: &Path) -> PathBuf {
+        match &self.root {
+            Some(root) if target.is_relative() => root.join(target),
+            _ => target.to_path_buf(),
+        }
+    }
+
+    pub fn with_config(mut self, config: DirConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// Give the process a writer for intermediate results of its transform
+    pub fn with_progress(mut self, progress: ProgressWriter) -> Self {
+        self.progress = Some(progress);
+        self
+    }
+
     pub fn new_with_origin(path: PathBuf, event_kind: EventKind, origin: EventOrigin) -> Self {
         Self {
+            relative_path: path.clone(),
             path,
+            root: None,
             event_kind,
             origin,
+            config: DirConfig::new(),
+            progress: None,
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_relative_path_without_root() {
+        let event = FileEvent::new(PathBuf::from("/srv/_mara/a/test.txt"), EventKind::Create);
+        assert_eq!(event.root, None);
+        assert_eq!(event.relative_path, PathBuf::from("/srv/_mara/a/test.txt"));
+    }
+
+    #[test]
+    fn test_with_root() {
+        let event = FileEvent::new(PathBuf::from("/srv/_mara/a/test.txt"), EventKind::Create)
+            .with_root(Path::new("/srv/_mara"));
+        assert_eq!(event.root, Some(PathBuf::from("/srv/_mara")));
+        assert_eq!(event.relative_path, PathBuf::from("a/test.txt"));
+    }
+
+    #[test]
+    fn test_resolve_against_root() {
+        let event = FileEvent::new(PathBuf::from("/srv/_mara/a/test.txt"), EventKind::Create)
+            .with_root(Path::new("/srv/_mara"));
+        assert_eq!(event.resolve(Path::new("b/test.txt")), PathBuf::from("/srv/_mara/b/test.txt"));
+        assert_eq!(event.resolve(Path::new("/tmp/test.txt")), PathBuf::from("/tmp/test.txt"));
+    }
+}
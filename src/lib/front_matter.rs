@@ -0,0 +1,137 @@
+/// FrontMatter struct - `key: value` lines between two `---` lines at the top of a file
+///
+/// Format:
+/// ---
+/// model: gpt-4o
+/// temperature: 0.7
+/// ---
+/// <body>
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct FrontMatter {
+    pub entries: Vec<(String, String)>,
+}
+
+impl FrontMatter {
+    pub fn new() -> Self {
+        FrontMatter {
+            entries: Vec::new(),
+        }
+    }
+
+    /// Split content into front matter and body
+    /// Without a complete block at the very top the whole content is the body
+    pub fn split(content: &str) -> (Option<FrontMatter>, &str) {
+        let Some(rest) = content
+            .strip_prefix("---\n")
+            .or_else(|| content.strip_prefix("---\r\n"))
+        else {
+            return (None, content);
+        };
+
+        let mut front_matter = FrontMatter::new();
+        let mut offset = 0;
+
+        for line in rest.split_inclusive('\n') {
+            offset += line.len();
+            let trimmed = line.trim();
+
+            if trimmed == "---" {
+                return (Some(front_matter), &rest[offset..]);
+            }
+
+            if trimmed.is_empty() || trimmed.starts_with('#') {
+                continue;
+            }
+
+            // Not a key-value line, so this is no front matter
+            let Some((key, value)) = trimmed.split_once(':') else {
+                return (None, content);
+            };
+            front_matter.set(key.trim(), value.trim());
+        }
+
+        // Block was never closed
+        (None, content)
+    }
+
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.entries
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// Set a value, existing keys keep their position
+    pub fn set(&mut self, key: &str, value: &str) {
+        match self.entries.iter_mut().find(|(k, _)| k == key) {
+            Some(entry) => entry.1 = value.to_string(),
+            None => self.entries.push((key.to_string(), value.to_string())),
+        }
+    }
+
+    pub fn remove(&mut self, key: &str) {
+        self.entries.retain(|(k, _)| k != key);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Render the block including both `---` lines
+    pub fn render(&self) -> String {
+        let mut output = String::from("---\n");
+        for (key, value) in &self.entries {
+            output.push_str(&format!("{}: {}\n", key, value));
+        }
+        output.push_str("---\n");
+        output
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_front_matter() {
+        let (front_matter, body) = FrontMatter::split("---\nmodel: gpt-4o\ntemperature: 0.7\n---\nDu bist mara.\n");
+        let front_matter = front_matter.unwrap();
+        assert_eq!(front_matter.get("model"), Some("gpt-4o"));
+        assert_eq!(front_matter.get("temperature"), Some("0.7"));
+        assert_eq!(body, "Du bist mara.\n");
+    }
+
+    #[test]
+    fn test_split_without_front_matter() {
+        let content = "User:\nhallo\n------\n";
+        let (front_matter, body) = FrontMatter::split(content);
+        assert_eq!(front_matter, None);
+        assert_eq!(body, content);
+    }
+
+    #[test]
+    fn test_split_unclosed_block() {
+        let content = "---\nmodel: gpt-4o\nhallo";
+        assert_eq!(FrontMatter::split(content), (None, content));
+    }
+
+    #[test]
+    fn test_split_separator_is_not_front_matter() {
+        let content = "---\nEinfach eine Linie\n---\n";
+        assert_eq!(FrontMatter::split(content), (None, content));
+    }
+
+    #[test]
+    fn test_value_with_colon() {
+        let (front_matter, _) = FrontMatter::split("---\nsystem: Antworte so: kurz\n---\n");
+        assert_eq!(front_matter.unwrap().get("system"), Some("Antworte so: kurz"));
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let original = "---\nmodel: gpt-4o\nmax_tokens: 500\n---\n";
+        let (front_matter, body) = FrontMatter::split(original);
+        assert_eq!(body, "");
+        assert_eq!(front_matter.unwrap().render(), original);
+    }
+}
@@ -0,0 +1,7 @@
+/// FNV-1a, stable across builds and Rust releases unlike DefaultHasher,
+/// for hashes that are stored on disk
+pub fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
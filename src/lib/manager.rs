@@ -1,20 +1,139 @@
+use super::config::DirConfig;
 use super::events::{EventKind, EventOrigin, FileEvent};
+use super::pause::PauseState;
 use super::process::SyncProcess;
-use notify::event::ModifyKind;
+use notify::event::{MetadataKind, ModifyKind};
 use notify::recommended_watcher;
-use notify::{RecursiveMode, Result as NotifyResult, Watcher};
+use notify::{Config, PollWatcher, RecursiveMode, Result as NotifyResult, Watcher};
 use std::fs;
-use std::path::Path;
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 
+/// Default interval of the polling backend
+pub const DEFAULT_POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+#[derive(Debug, Clone)]
 pub struct TargetMapping {
     pub target_path: std::path::PathBuf,
     pub process_name: String,
+    pub content_hash: Option<u64>, // Hash des zuletzt geschriebenen Inhalts, None bei Delete
+}
+
+impl TargetMapping {
+    /// Remember a write (or delete with `None`) of a process
+    /// Only the latest write per target is kept, events for it count as internal
+    /// as long as the file still has the written content
+    pub fn register(
+        mappings: &mut Vec<TargetMapping>,
+        target_path: &Path,
+        process_name: &str,
+        content: Option<&[u8]>,
+    ) {
+        mappings.retain(|m| m.target_path != target_path);
+        mappings.push(TargetMapping {
+            target_path: target_path.to_path_buf(),
+            process_name: process_name.to_string(),
+            content_hash: content.map(hash_content),
+        });
+    }
+}
+
+fn hash_content(content: &[u8]) -> u64 {
+    let mut hasher = std::collections::hash_map::DefaultHasher::new();
+    content.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// ProgressWriter - lets a process write intermediate results to its target while it is
+/// still transforming, e.g. a streamed chat reply. Every write is registered as internal.
+#[derive(Debug, Clone)]
+pub struct ProgressWriter {
+    target_path: PathBuf,
+    process_name: String,
+    target_mappings: std::sync::Arc<std::sync::Mutex<Vec<TargetMapping>>>,
+}
+
+impl ProgressWriter {
+    pub fn new(
+        target_path: &Path,
+        process_name: &str,
+        target_mappings: std::sync::Arc<std::sync::Mutex<Vec<TargetMapping>>>,
+    ) -> Self {
+        ProgressWriter {
+            target_path: target_path.to_path_buf(),
+            process_name: process_name.to_string(),
+            target_mappings,
+        }
+    }
+
+    pub fn write(&self, content: &[u8]) -> std::io::Result<()> {
+        TargetMapping::register(
+            &mut self.target_mappings.lock().unwrap(),
+            &self.target_path,
+            &self.process_name,
+            Some(content),
+        );
+        fs::write(&self.target_path, content)
+    }
+}
+
+/// Backend used to detect file changes, both feed the same dispatch path
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum WatcherBackend {
+    #[default]
+    Native,                        // inotify / FSEvents / ReadDirectoryChangesW
+    Poll { interval: Duration },   // Scannt regelmäßig, funktioniert auch auf NFS, SMB, sshfs und Docker Mounts
+}
+
+impl WatcherBackend {
+    /// Read the backend from the environment
+    /// MARA_WATCHER=native|poll, MARA_POLL_INTERVAL_MS=<milliseconds>
+    pub fn from_env() -> Result<Self, String> {
+        Self::from_vars(
+            std::env::var("MARA_WATCHER").ok().as_deref(),
+            std::env::var("MARA_POLL_INTERVAL_MS").ok().as_deref(),
+        )
+    }
+
+    /// Backend from the values of MARA_WATCHER and MARA_POLL_INTERVAL_MS,
+    /// the interval is only read for the polling backend
+    fn from_vars(watcher: Option<&str>, interval_ms: Option<&str>) -> Result<Self, String> {
+        match watcher {
+            None | Some("native") => Ok(WatcherBackend::Native),
+            Some("poll") => {
+                let interval = match interval_ms {
+                    Some(ms) => Duration::from_millis(
+                        ms.parse()
+                            .map_err(|_| format!("Invalid MARA_POLL_INTERVAL_MS: {}", ms))?,
+                    ),
+                    None => DEFAULT_POLL_INTERVAL,
+                };
+                Ok(WatcherBackend::Poll { interval })
+            }
+            Some(other) => Err(format!("Unknown MARA_WATCHER backend: {}", other)),
+        }
+    }
+}
+
+/// Event kind of a notify event, None for events that are ignored
+fn event_kind(kind: &notify::EventKind) -> Option<EventKind> {
+    match kind {
+        notify::EventKind::Create(_) => Some(EventKind::Create),
+        notify::EventKind::Modify(ModifyKind::Data(_)) => Some(EventKind::Modify),
+        // The polling backend reports content changes as a new write time
+        notify::EventKind::Modify(ModifyKind::Metadata(MetadataKind::WriteTime)) => Some(EventKind::Modify),
+        notify::EventKind::Remove(_) => Some(EventKind::Delete),
+        _ => None,
+    }
 }
 
+#[derive(Default)]
 pub struct Manager {
     watch_paths: Vec<String>,
     processes: Vec<SyncProcess>,
     target_mappings: Vec<TargetMapping>,
+    backend: WatcherBackend,
 }
 
 impl Manager {
@@ -23,9 +142,15 @@ impl Manager {
             watch_paths: Vec::new(),
             processes: Vec::new(),
             target_mappings: Vec::new(),
+            backend: WatcherBackend::Native,
         }
     }
 
+    pub fn watcher_backend(mut self, backend: WatcherBackend) -> Self {
+        self.backend = backend;
+        self
+    }
+
     pub fn watch_path(mut self, path: &str) -> Self {
         self.watch_paths.push(path.to_string());
         self
@@ -59,61 +184,60 @@ impl Manager {
         let target_mappings = std::sync::Arc::new(std::sync::Mutex::new(self.target_mappings));
         let target_mappings_clone = std::sync::Arc::clone(&target_mappings);
 
-        let watch_paths = self.watch_paths.clone();
-
-        let mut watcher = recommended_watcher(move |res: NotifyResult<notify::Event>| match res {
-            Ok(event) => match event.kind {
-                notify::EventKind::Create(_) => {
-                    for path in &event.paths {
-                        if path.is_file() {
-                            Self::dispatch_event(
-                                path.clone(),
-                                EventKind::Create,
-                                &processes_clone,
-                                &target_mappings_clone,
-                            );
-                        }
-                    }
-                }
-                notify::EventKind::Modify(kind) => {
-                    for path in &event.paths {
-                        if path.is_file() {
-                            match kind {
-                                ModifyKind::Any => {}
-                                ModifyKind::Data(_) => {
-                                    Self::dispatch_event(
-                                        path.clone(),
-                                        EventKind::Modify,
-                                        &processes_clone,
-                                        &target_mappings_clone,
-                                    );
-                                }
-                                ModifyKind::Metadata(_) => {}
-                                ModifyKind::Name(_) => {}
-                                ModifyKind::Other => {}
-                            }
-                        }
-                    }
-                }
-                notify::EventKind::Remove(_) => {
-                    for path in &event.paths {
-                        Self::dispatch_event(
-                            path.clone(),
-                            EventKind::Delete,
-                            &processes_clone,
-                            &target_mappings_clone,
-                        );
+        let pause_state = std::sync::Arc::new(std::sync::Mutex::new(PauseState::new()));
+
+        // Canonical roots, so event paths are absolute and can be matched against them
+        let watch_roots: Vec<PathBuf> = self
+            .watch_paths
+            .iter()
+            .map(|p| fs::canonicalize(p).unwrap_or_else(|_| PathBuf::from(p)))
+            .collect();
+        let watch_roots_clone = watch_roots.clone();
+
+        let handler = move |res: NotifyResult<notify::Event>| match res {
+            Ok(event) => {
+                let Some(event_kind) = event_kind(&event.kind) else {
+                    return;
+                };
+
+                for path in &event.paths {
+                    // Create and modify are only relevant for files, deletes can't be checked anymore
+                    if event_kind != EventKind::Delete && !path.is_file() {
+                        continue;
                     }
+
+                    Self::handle_event(
+                        path.clone(),
+                        event_kind,
+                        &watch_roots_clone,
+                        &pause_state,
+                        &processes_clone,
+                        &target_mappings_clone,
+                    );
                 }
-                _ => {}
-            },
+            }
             Err(e) => println!("Watcher error: {}", e),
-        })?;
+        };
+
+        let mut watcher: Box<dyn Watcher> = match self.backend {
+            WatcherBackend::Native => Box::new(recommended_watcher(handler)?),
+            WatcherBackend::Poll { interval } => Box::new(PollWatcher::new(
+                handler,
+                Config::default().with_poll_interval(interval),
+            )?),
+        };
 
         // Watch all configured paths
-        for path in &watch_paths {
-            watcher.watch(Path::new(path), RecursiveMode::Recursive)?;
-            println!("Watching: {}", path);
+        for root in &watch_roots {
+            watcher.watch(root, RecursiveMode::Recursive)?;
+            match self.backend {
+                WatcherBackend::Native => println!("Watching: {}", root.display()),
+                WatcherBackend::Poll { interval } => println!(
+                    "Watching: {} (polling every {} ms)",
+                    root.display(),
+                    interval.as_millis()
+                ),
+            }
         }
 
         println!("Sync manager running. Press Ctrl+C to stop.");
@@ -124,43 +248,95 @@ impl Manager {
         }
     }
 
+    /// Route an event through the pause handling of its watch root before dispatching it
+    fn handle_event(
+        path: PathBuf,
+        event_kind: EventKind,
+        watch_roots: &[PathBuf],
+        pause_state: &std::sync::Arc<std::sync::Mutex<PauseState>>,
+        processes: &std::sync::Arc<Vec<SyncProcess>>,
+        target_mappings: &std::sync::Arc<std::sync::Mutex<Vec<TargetMapping>>>,
+    ) {
+        let Some(root) = watch_roots.iter().find(|root| path.starts_with(root)) else {
+            Self::dispatch_event(path, event_kind, None, processes, target_mappings);
+            return;
+        };
+
+        if PauseState::is_sentinel(root, &path) {
+            match event_kind {
+                EventKind::Create => println!("PAUSED | {}", root.display()),
+                EventKind::Delete => {
+                    let queued = pause_state.lock().unwrap().take(root);
+                    println!("RESUMED | {} ({} queued events)", root.display(), queued.len());
+
+                    for (queued_path, queued_kind) in queued {
+                        // The file may have been removed after its last queued event
+                        if queued_kind != EventKind::Delete && !queued_path.is_file() {
+                            continue;
+                        }
+                        Self::dispatch_event(
+                            queued_path,
+                            queued_kind,
+                            Some(root),
+                            processes,
+                            target_mappings,
+                        );
+                    }
+                }
+                EventKind::Modify => {}
+            }
+            return;
+        }
+
+        if PauseState::is_paused(root) {
+            pause_state.lock().unwrap().queue(root, path, event_kind);
+            return;
+        }
+
+        Self::dispatch_event(path, event_kind, Some(root), processes, target_mappings);
+    }
+
     fn dispatch_event(
         path: std::path::PathBuf,
         event_kind: EventKind,
+        root: Option<&Path>,
         processes: &std::sync::Arc<Vec<SyncProcess>>,
         target_mappings: &std::sync::Arc<std::sync::Mutex<Vec<TargetMapping>>>,
     ) {
         // Check if this path is a target path from a previous operation (Internal origin)
         let mut mappings = target_mappings.lock().unwrap();
         let mut origin = EventOrigin::External;
-        let mut matched_index = None;
-
-        for (index, mapping) in mappings.iter().enumerate() {
-            let absolute_target = if mapping.target_path.is_absolute() {
-                mapping.target_path.clone()
-            } else {
-                std::env::current_dir()
-                    .ok()
-                    .map(|cwd| cwd.join(&mapping.target_path))
-                    .unwrap_or_else(|| mapping.target_path.clone())
+
+        if let Some(index) = mappings.iter().position(|m| m.target_path == path) {
+            let mapping = &mappings[index];
+            let current_hash = match event_kind {
+                EventKind::Delete => None,
+                EventKind::Create | EventKind::Modify => fs::read(&path).ok().map(|c| hash_content(&c)),
             };
 
-            if absolute_target == path {
+            // Several events can belong to one write, they are all internal while the
+            // content is unchanged. A registered delete matches the delete event only.
+            if mapping.content_hash == current_hash {
                 origin = EventOrigin::Internal {
                     process_name: mapping.process_name.clone(),
                 };
-                matched_index = Some(index);
-                break;
             }
-        }
-
-        // Remove the matched mapping to prevent false matches in future events
-        if let Some(index) = matched_index {
-            mappings.remove(index);
+            if mapping.content_hash.is_none() || mapping.content_hash != current_hash {
+                mappings.remove(index);
+            }
         }
         drop(mappings);
 
-        let event = FileEvent::new_with_origin(path, event_kind, origin);
+        // Merge the .mara.toml files from the watch root down to the file's directory
+        let config = match path.parent() {
+            Some(dir) => DirConfig::load(root, dir),
+            None => DirConfig::new(),
+        };
+
+        let mut event = FileEvent::new_with_origin(path, event_kind, origin).with_config(config);
+        if let Some(root) = root {
+            event = event.with_root(root);
+        }
 
         // Log the event before processing
         let event_kind_str = match event.event_kind {
@@ -188,25 +364,38 @@ impl Manager {
                 continue;
             }
 
-            // 2. Get target path
+            // 2. Get target path, relative targets are resolved against the watch root
             let Some(target_path) = process.get_target(&event) else {
                 continue;
             };
+            let target_path = event.resolve(&target_path);
 
-            // 3. Add target mapping
-            {
-                let mut mappings = target_mappings.lock().unwrap();
-                mappings.push(TargetMapping {
-                    target_path: target_path.clone(),
-                    process_name: process.name.clone(),
-                });
-            }
-
-            // 4. Execute the sync
+            // 3. Execute the sync, every write is registered as target mapping first
             match event.event_kind {
                 EventKind::Create | EventKind::Modify => {
+                    let event = event.clone().with_progress(ProgressWriter::new(
+                        &target_path,
+                        &process.name,
+                        target_mappings.clone(),
+                    ));
+
                     if let Ok(content) = fs::read(&event.path) {
                         if let Ok(transformed) = process.transform_content(&event, &content) {
+                            // Rewriting a file with its own content only causes new events.
+                            // Read it again, the transform may have written progress meanwhile.
+                            if target_path == event.path
+                                && fs::read(&target_path).map(|c| c == transformed).unwrap_or(false)
+                            {
+                                continue;
+                            }
+
+                            TargetMapping::register(
+                                &mut target_mappings.lock().unwrap(),
+                                &target_path,
+                                &process.name,
+                                Some(&transformed),
+                            );
+
                             if let Err(e) = fs::write(&target_path, transformed) {
                                 println!("[{}] Error writing: {}", process.name, e);
                                 continue;
@@ -228,6 +417,13 @@ impl Manager {
                     }
                 }
                 EventKind::Delete => {
+                    TargetMapping::register(
+                        &mut target_mappings.lock().unwrap(),
+                        &target_path,
+                        &process.name,
+                        None,
+                    );
+
                     if target_path.exists() {
                         if let Err(e) = fs::remove_file(&target_path) {
                             println!("[{}] Delete error: {}", process.name, e);
@@ -248,3 +444,40 @@ impl Manager {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use notify::event::{CreateKind, DataChange, RemoveKind};
+
+    #[test]
+    fn test_backend_from_vars() {
+        assert_eq!(WatcherBackend::from_vars(None, None), Ok(WatcherBackend::Native));
+        assert_eq!(WatcherBackend::from_vars(Some("native"), None), Ok(WatcherBackend::Native));
+        // The interval only matters for polling
+        assert_eq!(WatcherBackend::from_vars(Some("native"), Some("bald")), Ok(WatcherBackend::Native));
+        assert_eq!(
+            WatcherBackend::from_vars(Some("poll"), None),
+            Ok(WatcherBackend::Poll { interval: DEFAULT_POLL_INTERVAL })
+        );
+        assert_eq!(
+            WatcherBackend::from_vars(Some("poll"), Some("250")),
+            Ok(WatcherBackend::Poll { interval: Duration::from_millis(250) })
+        );
+        assert!(WatcherBackend::from_vars(Some("poll"), Some("bald")).is_err());
+        assert!(WatcherBackend::from_vars(Some("inotify"), None).is_err());
+    }
+
+    #[test]
+    fn test_event_kind() {
+        let write_time = notify::EventKind::Modify(ModifyKind::Metadata(MetadataKind::WriteTime));
+        assert_eq!(event_kind(&write_time), Some(EventKind::Modify));
+        let data = notify::EventKind::Modify(ModifyKind::Data(DataChange::Content));
+        assert_eq!(event_kind(&data), Some(EventKind::Modify));
+        assert_eq!(event_kind(&notify::EventKind::Create(CreateKind::File)), Some(EventKind::Create));
+        assert_eq!(event_kind(&notify::EventKind::Remove(RemoveKind::File)), Some(EventKind::Delete));
+
+        let permissions = notify::EventKind::Modify(ModifyKind::Metadata(MetadataKind::Permissions));
+        assert_eq!(event_kind(&permissions), None);
+    }
+}
@@ -0,0 +1,241 @@
+use super::openai::OpenAIClient;
+use serde_json::{json, Value};
+use std::collections::VecDeque;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+/// Model of the clients created by `MockServer::client`
+pub const MOCK_MODEL: &str = "mock-model";
+
+/// Prompt tokens reported per request message
+const MOCK_TOKENS_PER_MESSAGE: u64 = 10;
+
+/// MockResponse enum - the scripted answer to the next request
+#[derive(Debug, Clone, PartialEq)]
+pub enum MockResponse {
+    /// Reply text, streamed word by word if the request asks for a stream
+    Reply(String),
+    /// Reply streamed in these chunks with a pause between them, e.g. to see progressive writes
+    Streamed(Vec<String>, Duration),
+    /// Calls of tools: name and JSON arguments
+    ToolCalls(Vec<(String, String)>),
+    /// Error status with an OpenAI error body
+    Error(u16, String),
+    /// 429 with a Retry-After header in seconds
+    RateLimited(u64),
+    /// Wait before answering, e.g. to run into a read timeout
+    Delayed(Duration, Box<MockResponse>),
+}
+
+#[derive(Default)]
+struct MockState {
+    responses: VecDeque<MockResponse>,
+    requests: Vec<Value>,
+}
+
+/// MockServer struct - a local chat completions server for tests, no network needed
+///
+/// Every request takes the next scripted response, without one it gets a 400.
+/// The server stops when it is dropped.
+pub struct MockServer {
+    addr: SocketAddr,
+    state: Arc<Mutex<MockState>>,
+    stopped: Arc<AtomicBool>,
+}
+
+impl MockServer {
+    pub fn start() -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("Mock server could not bind");
+        let addr = listener.local_addr().unwrap();
+        let state = Arc::new(Mutex::new(MockState::default()));
+        let stopped = Arc::new(AtomicBool::new(false));
+
+        let (thread_state, thread_stopped) = (state.clone(), stopped.clone());
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                if thread_stopped.load(Ordering::SeqCst) {
+                    break;
+                }
+                let Ok(stream) = stream else { continue };
+                let state = thread_state.clone();
+                thread::spawn(move || handle(stream, &state));
+            }
+        });
+
+        MockServer { addr, state, stopped }
+    }
+
+    /// Add a scripted response, they are used in order
+    pub fn with_response(self, response: MockResponse) -> Self {
+        self.push(response);
+        self
+    }
+
+    pub fn push(&self, response: MockResponse) {
+        self.state.lock().unwrap().responses.push_back(response);
+    }
+
+    /// Base URL for OpenAIClient and `[chat] base_url`
+    pub fn base_url(&self) -> String {
+        format!("http://{}/v1", self.addr)
+    }
+
+    pub fn client(&self) -> OpenAIClient {
+        OpenAIClient::for_base_url(&self.base_url(), MOCK_MODEL)
+    }
+
+    /// JSON bodies of the requests received so far
+    pub fn requests(&self) -> Vec<Value> {
+        self.state.lock().unwrap().requests.clone()
+    }
+}
+
+impl Drop for MockServer {
+    fn drop(&mut self) {
+        self.stopped.store(true, Ordering::SeqCst);
+        // Wake up the accept loop so it sees the flag
+        let _ = TcpStream::connect(self.addr);
+    }
+}
+
+/// Read one request and answer it with the next scripted response
+fn handle(mut stream: TcpStream, state: &Mutex<MockState>) {
+    let Some((path, body)) = read_request(&stream) else {
+        return;
+    };
+    let request: Value = serde_json::from_slice(&body).unwrap_or(Value::Null);
+
+    let response = {
+        let mut state = state.lock().unwrap();
+        state.requests.push(request.clone());
+        state.responses.pop_front()
+    };
+
+    let result = match response {
+        _ if !path.ends_with("/chat/completions") => write_error(&mut stream, 404, &format!("Unknown path {}", path)),
+        Some(response) => respond(&mut stream, &request, response),
+        None => write_error(&mut stream, 400, "Keine Antwort vorbereitet"),
+    };
+    if let Err(e) = result {
+        eprintln!("Mock server: {}", e);
+    }
+}
+
+fn read_request(stream: &TcpStream) -> Option<(String, Vec<u8>)> {
+    let mut reader = BufReader::new(stream);
+    let mut line = String::new();
+    reader.read_line(&mut line).ok()?;
+    let path = line.split_whitespace().nth(1)?.to_string();
+
+    let mut length = 0;
+    loop {
+        let mut header = String::new();
+        reader.read_line(&mut header).ok()?;
+        let header = header.trim();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.eq_ignore_ascii_case("content-length") {
+                length = value.trim().parse().unwrap_or(0);
+            }
+        }
+    }
+
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body).ok()?;
+    Some((path, body))
+}
+
+fn respond(stream: &mut TcpStream, request: &Value, response: MockResponse) -> std::io::Result<()> {
+    let prompt_tokens = request["messages"].as_array().map(|m| m.len() as u64).unwrap_or(0) * MOCK_TOKENS_PER_MESSAGE;
+
+    match response {
+        MockResponse::Reply(text) if request["stream"] == json!(true) => {
+            let include_usage = request["stream_options"]["include_usage"] == json!(true);
+            let words: Vec<String> = text.split_inclusive(' ').map(String::from).collect();
+            write_stream(stream, &words, Duration::ZERO, include_usage.then_some(prompt_tokens))
+        }
+        MockResponse::Streamed(chunks, pause) if request["stream"] == json!(true) => {
+            let include_usage = request["stream_options"]["include_usage"] == json!(true);
+            write_stream(stream, &chunks, pause, include_usage.then_some(prompt_tokens))
+        }
+        MockResponse::Streamed(chunks, _) => respond(stream, request, MockResponse::Reply(chunks.concat())),
+        MockResponse::Reply(text) => {
+            let completion_tokens = text.split_whitespace().count() as u64;
+            let body = json!({
+                "choices": [{"index": 0, "message": {"role": "assistant", "content": text}, "finish_reason": "stop"}],
+                "usage": {"prompt_tokens": prompt_tokens, "completion_tokens": completion_tokens},
+            });
+            write_json(stream, 200, &[], &body)
+        }
+        MockResponse::ToolCalls(calls) => {
+            let tool_calls: Vec<Value> = calls
+                .iter()
+                .enumerate()
+                .map(|(i, (name, arguments))| {
+                    json!({"id": format!("call_{}", i), "type": "function", "function": {"name": name, "arguments": arguments}})
+                })
+                .collect();
+            let body = json!({
+                "choices": [{"index": 0, "message": {"role": "assistant", "content": null, "tool_calls": tool_calls}, "finish_reason": "tool_calls"}],
+                "usage": {"prompt_tokens": prompt_tokens, "completion_tokens": calls.len() as u64},
+            });
+            write_json(stream, 200, &[], &body)
+        }
+        MockResponse::Error(status, message) => write_error(stream, status, &message),
+        MockResponse::RateLimited(retry_after) => {
+            let body = json!({"error": {"message": "Rate limit reached"}});
+            write_json(stream, 429, &[("Retry-After", retry_after.to_string())], &body)
+        }
+        MockResponse::Delayed(delay, response) => {
+            thread::sleep(delay);
+            respond(stream, request, *response)
+        }
+    }
+}
+
+fn write_error(stream: &mut TcpStream, status: u16, message: &str) -> std::io::Result<()> {
+    write_json(stream, status, &[], &json!({"error": {"message": message}}))
+}
+
+fn write_json(stream: &mut TcpStream, status: u16, headers: &[(&str, String)], body: &Value) -> std::io::Result<()> {
+    let body = body.to_string();
+    let mut head = format!(
+        "HTTP/1.1 {} Mock\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n",
+        status,
+        body.len()
+    );
+    for (name, value) in headers {
+        head.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    head.push_str("\r\n");
+
+    stream.write_all(head.as_bytes())?;
+    stream.write_all(body.as_bytes())?;
+    stream.flush()
+}
+
+/// Server-sent events, one per text chunk, the usage in a last chunk without choices
+fn write_stream(stream: &mut TcpStream, texts: &[String], pause: Duration, prompt_tokens: Option<u64>) -> std::io::Result<()> {
+    stream.write_all(b"HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nConnection: close\r\n\r\n")?;
+
+    for (i, text) in texts.iter().enumerate() {
+        if i > 0 {
+            thread::sleep(pause);
+        }
+        let chunk = json!({"choices": [{"index": 0, "delta": {"content": text}}]});
+        stream.write_all(format!("data: {}\n\n", chunk).as_bytes())?;
+        stream.flush()?;
+    }
+    if let Some(prompt_tokens) = prompt_tokens {
+        let chunk = json!({"choices": [], "usage": {"prompt_tokens": prompt_tokens, "completion_tokens": texts.len()}});
+        stream.write_all(format!("data: {}\n\n", chunk).as_bytes())?;
+    }
+    stream.write_all(b"data: [DONE]\n\n")?;
+    stream.flush()
+}
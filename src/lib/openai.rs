@@ -1,10 +1,66 @@
+use super::config::DirConfig;
+use super::provider::{Completion, CompletionOptions, EmbeddingProvider, LlmMessage, LlmProvider, ToolCall, ToolSpec, Usage};
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::env;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Base URL of the official OpenAI API
+pub const OPENAI_BASE_URL: &str = "https://api.openai.com/v1";
+
+pub const DEFAULT_CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+pub const DEFAULT_READ_TIMEOUT: Duration = Duration::from_secs(60);
+pub const DEFAULT_MAX_RETRIES: u32 = 3;
+pub const DEFAULT_EMBEDDING_MODEL: &str = "text-embedding-3-small";
+
+const RETRY_BASE_DELAY: Duration = Duration::from_millis(500);
+const RETRY_MAX_DELAY: Duration = Duration::from_secs(30);
 
 #[derive(Debug, Serialize, Deserialize)]
 struct OpenAIMessage {
     role: String,
-    content: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    name: Option<String>,
+    #[serde(default)]
+    content: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    tool_calls: Vec<OpenAIToolCall>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    tool_call_id: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct OpenAIToolCall {
+    id: String,
+    #[serde(rename = "type", default = "function_type")]
+    kind: String,
+    function: OpenAIFunctionCall,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct OpenAIFunctionCall {
+    name: String,
+    #[serde(default)]
+    arguments: String,
+}
+
+#[derive(Debug, Serialize)]
+struct OpenAITool {
+    #[serde(rename = "type")]
+    kind: String,
+    function: OpenAIFunction,
+}
+
+#[derive(Debug, Serialize)]
+struct OpenAIFunction {
+    name: String,
+    description: String,
+    parameters: serde_json::Value,
+}
+
+fn function_type() -> String {
+    "function".to_string()
 }
 
 #[derive(Debug, Serialize)]
@@ -12,6 +68,50 @@ struct OpenAIRequest {
     model: String,
     messages: Vec<OpenAIMessage>,
     temperature: f32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    max_tokens: Option<u32>,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    stream: bool,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    tools: Vec<OpenAITool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    stream_options: Option<OpenAIStreamOptions>,
+}
+
+#[derive(Debug, Serialize)]
+struct OpenAIStreamOptions {
+    include_usage: bool,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+struct OpenAIUsage {
+    #[serde(default)]
+    prompt_tokens: u64,
+    #[serde(default)]
+    completion_tokens: u64,
+}
+
+#[derive(Debug, Serialize)]
+struct OpenAIEmbeddingRequest {
+    model: String,
+    input: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct OpenAIEmbedding {
+    #[serde(default)]
+    index: usize,
+    embedding: Vec<f32>,
+}
+
+#[derive(Debug, Deserialize)]
+struct OpenAIEmbeddingResponse {
+    #[serde(default)]
+    data: Vec<OpenAIEmbedding>,
+    #[serde(default)]
+    error: Option<OpenAIError>,
+    #[serde(default)]
+    usage: Option<OpenAIUsage>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -28,6 +128,8 @@ struct OpenAIResponse {
     choices: Vec<OpenAIChoice>,
     #[serde(default)]
     error: Option<OpenAIError>,
+    #[serde(default)]
+    usage: Option<OpenAIUsage>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -35,58 +137,289 @@ struct OpenAIError {
     message: String,
 }
 
+#[derive(Debug, Deserialize)]
+struct OpenAIStreamChunk {
+    #[serde(default)]
+    choices: Vec<OpenAIStreamChoice>,
+    #[serde(default)]
+    error: Option<OpenAIError>,
+    #[serde(default)]
+    usage: Option<OpenAIUsage>,
+}
+
+#[derive(Debug, Deserialize)]
+struct OpenAIStreamChoice {
+    #[serde(default)]
+    delta: Option<OpenAIDelta>,
+}
+
+#[derive(Debug, Deserialize)]
+struct OpenAIDelta {
+    #[serde(default)]
+    content: Option<String>,
+}
+
+/// SseParser - splits a server-sent-event byte stream into `data:` payloads
+#[derive(Debug, Default)]
+pub struct SseParser {
+    buffer: Vec<u8>,
+}
+
+impl SseParser {
+    pub fn new() -> Self {
+        SseParser { buffer: Vec::new() }
+    }
+
+    /// Feed received bytes, returns the payloads of all complete `data:` lines
+    pub fn push(&mut self, bytes: &[u8]) -> Vec<String> {
+        self.buffer.extend_from_slice(bytes);
+        let mut payloads = Vec::new();
+
+        while let Some(pos) = self.buffer.iter().position(|&b| b == b'\n') {
+            let line: Vec<u8> = self.buffer.drain(..=pos).collect();
+            let line = String::from_utf8_lossy(&line);
+            if let Some(data) = line.trim_end().strip_prefix("data:") {
+                payloads.push(data.trim_start().to_string());
+            }
+        }
+
+        payloads
+    }
+}
+
+/// OpenAIClient - talks to any server with the OpenAI chat completions API
+/// (OpenAI, llama.cpp, Ollama, vLLM, test stubs)
 pub struct OpenAIClient {
-    api_key: String,
+    api_key: Option<String>,
+    base_url: String,
     model: String,
+    embedding_model: String,
+    headers: Vec<(String, String)>,
+    http: reqwest::Client,      // reused for all requests of this client
+    connect_timeout: Duration,
+    read_timeout: Duration,     // waiting for the response and for every stream chunk
+    max_retries: u32,
+    usage: Mutex<BTreeMap<String, Usage>>,
 }
 
 impl OpenAIClient {
+    /// Create a client from the environment (.env)
+    /// OPENAI_BASE_URL: defaults to the OpenAI API, OPENAI_API_KEY is only required there
+    /// OPENAI_MODEL: defaults to gpt-4
+    /// OPENAI_HEADERS: extra headers, `Name: value` separated by `;`
     pub fn new() -> Result<Self, String> {
+        Self::from_env(None)
+    }
+
+    /// Create a client from the environment, `base_url` overrides OPENAI_BASE_URL
+    pub fn from_env(base_url: Option<&str>) -> Result<Self, String> {
         dotenv::dotenv().ok();
 
-        let api_key = env::var("OPENAI_API_KEY")
-            .map_err(|_| "OPENAI_API_KEY not found in .env".to_string())?;
+        let base_url = match base_url {
+            Some(base_url) => base_url.to_string(),
+            None => env::var("OPENAI_BASE_URL").unwrap_or_else(|_| OPENAI_BASE_URL.to_string()),
+        };
+
+        let api_key = match env::var("OPENAI_API_KEY") {
+            Ok(key) => Some(key),
+            Err(_) if base_url == OPENAI_BASE_URL => {
+                return Err("OPENAI_API_KEY not found in .env".to_string());
+            }
+            Err(_) => None,
+        };
 
         let model = env::var("OPENAI_MODEL")
             .unwrap_or_else(|_| "gpt-4".to_string());
 
-        Ok(OpenAIClient { api_key, model })
+        let headers = env::var("OPENAI_HEADERS")
+            .map(|headers| parse_headers(&headers))
+            .unwrap_or_default();
+
+        Ok(OpenAIClient::for_base_url(&base_url, &model).with_headers(api_key, headers))
+    }
+
+    /// Create a client from the environment and the [chat] section of .mara.toml
+    /// [chat]
+    /// model = "llama3"
+    /// base_url = "http://localhost:11434/v1"
+    /// connect_timeout_secs = 10
+    /// read_timeout_secs = 60
+    /// max_retries = 3
+    pub fn from_config(config: &DirConfig) -> Result<Self, String> {
+        let mut client = Self::from_env(config.get_str("chat", "base_url"))?;
+
+        if let Some(model) = config.get_str("chat", "model") {
+            client = client.with_model(model);
+        }
+
+        let secs = |key: &str, default: Duration| {
+            config
+                .get_int("chat", key)
+                .map(|s| Duration::from_secs(s.max(1) as u64))
+                .unwrap_or(default)
+        };
+        client = client.with_timeouts(
+            secs("connect_timeout_secs", DEFAULT_CONNECT_TIMEOUT),
+            secs("read_timeout_secs", DEFAULT_READ_TIMEOUT),
+        );
+        if let Some(max_retries) = config.get_int("chat", "max_retries") {
+            client = client.with_max_retries(max_retries.max(0) as u32);
+        }
+
+        Ok(client)
+    }
+
+    fn with_headers(mut self, api_key: Option<String>, headers: Vec<(String, String)>) -> Self {
+        self.api_key = api_key;
+        self.headers = headers;
+        self
+    }
+
+    /// Create a client for a server that needs no API key, e.g. a local model or a test stub
+    pub fn for_base_url(base_url: &str, model: &str) -> Self {
+        OpenAIClient {
+            api_key: None,
+            base_url: base_url.to_string(),
+            model: model.to_string(),
+            embedding_model: DEFAULT_EMBEDDING_MODEL.to_string(),
+            headers: Vec::new(),
+            http: http_client(DEFAULT_CONNECT_TIMEOUT),
+            connect_timeout: DEFAULT_CONNECT_TIMEOUT,
+            read_timeout: DEFAULT_READ_TIMEOUT,
+            max_retries: DEFAULT_MAX_RETRIES,
+            usage: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    pub fn with_model(mut self, model: &str) -> Self {
+        self.model = model.to_string();
+        self
+    }
+
+    pub fn with_embedding_model(mut self, model: &str) -> Self {
+        self.embedding_model = model.to_string();
+        self
+    }
+
+    pub fn with_base_url(mut self, base_url: &str) -> Self {
+        self.base_url = base_url.to_string();
+        self
+    }
+
+    pub fn with_api_key(mut self, api_key: &str) -> Self {
+        self.api_key = Some(api_key.to_string());
+        self
+    }
+
+    pub fn with_header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
     }
 
-    pub async fn generate_response(&self, messages: Vec<(String, String)>) -> Result<String, String> {
-        let openai_messages: Vec<OpenAIMessage> = messages
+    pub fn with_timeouts(mut self, connect_timeout: Duration, read_timeout: Duration) -> Self {
+        if connect_timeout != self.connect_timeout {
+            self.http = http_client(connect_timeout);
+            self.connect_timeout = connect_timeout;
+        }
+        self.read_timeout = read_timeout;
+        self
+    }
+
+    /// Retries of requests that failed with 429, 5xx, a timeout or a connection error
+    pub fn with_max_retries(mut self, max_retries: u32) -> Self {
+        self.max_retries = max_retries;
+        self
+    }
+
+    /// Add the usage reported for a request
+    fn record_usage(&self, model: &str, usage: OpenAIUsage) {
+        self.usage.lock().unwrap().entry(model.to_string()).or_default().add(Usage {
+            prompt_tokens: usage.prompt_tokens,
+            completion_tokens: usage.completion_tokens,
+            requests: 1,
+        });
+    }
+
+    pub fn base_url(&self) -> &str {
+        &self.base_url
+    }
+
+    /// URL of the chat completions endpoint
+    pub fn endpoint(&self) -> String {
+        format!("{}/chat/completions", self.base_url.trim_end_matches('/'))
+    }
+
+    /// URL of the embeddings endpoint
+    pub fn embeddings_endpoint(&self) -> String {
+        format!("{}/embeddings", self.base_url.trim_end_matches('/'))
+    }
+
+    fn build_request(
+        &self,
+        messages: Vec<LlmMessage>,
+        options: CompletionOptions,
+        stream: bool,
+    ) -> OpenAIRequest {
+        let system = options.system.map(|content| OpenAIMessage {
+            role: "system".to_string(),
+            name: None,
+            content: Some(content),
+            tool_calls: Vec::new(),
+            tool_call_id: None,
+        });
+
+        let openai_messages: Vec<OpenAIMessage> = system
             .into_iter()
-            .map(|(persona, content)| OpenAIMessage {
-                role: if persona == "User" {
-                    "user".to_string()
+            .chain(messages.into_iter().map(|message| OpenAIMessage {
+                role: message.role.as_str().to_string(),
+                name: message.name.as_deref().map(sanitize_name),
+                // Tool call requests may come without text
+                content: if message.content.is_empty() && !message.tool_calls.is_empty() {
+                    None
                 } else {
-                    "assistant".to_string()
+                    Some(message.content)
                 },
-                content,
-            })
+                tool_calls: message
+                    .tool_calls
+                    .into_iter()
+                    .map(|call| OpenAIToolCall {
+                        id: call.id,
+                        kind: function_type(),
+                        function: OpenAIFunctionCall {
+                            name: call.name,
+                            arguments: call.arguments,
+                        },
+                    })
+                    .collect(),
+                tool_call_id: message.tool_call_id,
+            }))
             .collect();
 
-        let request = OpenAIRequest {
-            model: self.model.clone(),
+        OpenAIRequest {
+            model: options.model.unwrap_or_else(|| self.model.clone()),
             messages: openai_messages,
-            temperature: 1.0,
-        };
+            temperature: options.temperature.unwrap_or(1.0),
+            max_tokens: options.max_tokens,
+            stream,
+            tools: Vec::new(),
+            stream_options: stream.then_some(OpenAIStreamOptions { include_usage: true }),
+        }
+    }
 
-        let client = reqwest::Client::new();
-        let response = client
-            .post("https://api.openai.com/v1/chat/completions")
-            .header("Authorization", format!("Bearer {}", self.api_key))
-            .json(&request)
-            .send()
-            .await
-            .map_err(|e| format!("Request failed: {}", e))?;
+    /// Send a non-streaming request and extract text or tool calls of the first choice
+    async fn complete(&self, request: &OpenAIRequest) -> Result<Completion, String> {
+        let response = self.send(&self.endpoint(), request).await?;
 
         let status = response.status();
-        let text = response
-            .text()
+        let text = tokio::time::timeout(self.read_timeout, response.text())
             .await
+            .map_err(|_| format!("Timeout after {}s while reading the response", self.read_timeout.as_secs()))?
             .map_err(|e| format!("Failed to get response text: {}", e))?;
 
+        if !status.is_success() {
+            return Err(api_error(status, &text));
+        }
+
         let data: OpenAIResponse = serde_json::from_str(&text)
             .map_err(|e| format!("Failed to parse response: {} - Response: {}", e, text))?;
 
@@ -94,12 +427,548 @@ impl OpenAIClient {
         if let Some(err) = data.error {
             return Err(format!("OpenAI API error: {}", err.message));
         }
+        if let Some(usage) = data.usage {
+            self.record_usage(&request.model, usage);
+        }
+
+        let Some(choice) = data.choices.into_iter().next() else {
+            return Err(format!("No response content from OpenAI. Status: {}", status));
+        };
+
+        let tool_calls: Vec<ToolCall> = choice
+            .message
+            .as_ref()
+            .map(|m| {
+                m.tool_calls
+                    .iter()
+                    .map(|call| ToolCall {
+                        id: call.id.clone(),
+                        name: call.function.name.clone(),
+                        arguments: call.function.arguments.clone(),
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
 
         // Extract response content
-        data.choices
-            .first()
-            .and_then(|choice| choice.message.as_ref().map(|m| m.content.clone()))
-            .or_else(|| data.choices.first().and_then(|choice| choice.text.clone()))
-            .ok_or_else(|| format!("No response content from OpenAI. Status: {}", status))
+        let content = choice
+            .message
+            .and_then(|m| m.content)
+            .or(choice.text);
+
+        match content {
+            Some(content) => Ok(Completion { content, tool_calls }),
+            None if !tool_calls.is_empty() => Ok(Completion {
+                content: String::new(),
+                tool_calls,
+            }),
+            None => Err(format!("No response content from OpenAI. Status: {}", status)),
+        }
+    }
+
+    /// Send a request with API key and extra headers
+    /// 429, 5xx, timeouts and connection errors are retried with jittered exponential
+    /// backoff, a `Retry-After` header of the server wins. Other statuses are returned.
+    async fn send<T: Serialize + Sync>(&self, url: &str, request: &T) -> Result<reqwest::Response, String> {
+        let mut attempt = 0;
+
+        loop {
+            let mut builder = self.http.post(url).json(request);
+            if let Some(api_key) = &self.api_key {
+                builder = builder.header("Authorization", format!("Bearer {}", api_key));
+            }
+            for (name, value) in &self.headers {
+                builder = builder.header(name.as_str(), value.as_str());
+            }
+
+            let (error, retry_after) = match tokio::time::timeout(self.read_timeout, builder.send()).await {
+                Ok(Ok(response)) if !is_retryable(response.status()) => return Ok(response),
+                Ok(Ok(response)) if attempt >= self.max_retries => return Ok(response),
+                Ok(Ok(response)) => (format!("Status {}", response.status()), retry_after(&response)),
+                Ok(Err(e)) if e.is_timeout() || e.is_connect() => (format!("Request failed: {}", e), None),
+                // Bad URLs, headers, bodies and redirects fail the same way again
+                Ok(Err(e)) => return Err(format!("Request failed: {}", e)),
+                Err(_) => (format!("Timeout after {}s", self.read_timeout.as_secs()), None),
+            };
+
+            if attempt >= self.max_retries {
+                return Err(error);
+            }
+
+            let delay = backoff_delay(attempt, retry_after, jitter());
+            eprintln!(
+                "OpenAI request failed ({}), retry {}/{} in {:.1}s",
+                error,
+                attempt + 1,
+                self.max_retries,
+                delay.as_secs_f32()
+            );
+            tokio::time::sleep(delay).await;
+            attempt += 1;
+        }
+    }
+}
+
+impl LlmProvider for OpenAIClient {
+    fn model(&self) -> &str {
+        &self.model
+    }
+
+    fn usage(&self) -> BTreeMap<String, Usage> {
+        self.usage.lock().unwrap().clone()
+    }
+
+    async fn generate_response(
+        &self,
+        messages: Vec<LlmMessage>,
+        options: CompletionOptions,
+    ) -> Result<String, String> {
+        let request = self.build_request(messages, options, false);
+        Ok(self.complete(&request).await?.content)
+    }
+
+    async fn generate_with_tools(
+        &self,
+        messages: Vec<LlmMessage>,
+        options: CompletionOptions,
+        tools: Vec<ToolSpec>,
+    ) -> Result<Completion, String> {
+        let mut request = self.build_request(messages, options, false);
+        request.tools = tools
+            .into_iter()
+            .map(|tool| OpenAITool {
+                kind: function_type(),
+                function: OpenAIFunction {
+                    name: tool.name,
+                    description: tool.description,
+                    parameters: tool.parameters,
+                },
+            })
+            .collect();
+
+        self.complete(&request).await
+    }
+
+    async fn generate_response_stream<F: FnMut(&str) + Send>(
+        &self,
+        messages: Vec<LlmMessage>,
+        options: CompletionOptions,
+        mut on_chunk: F,
+    ) -> Result<String, String> {
+        let request = self.build_request(messages, options, true);
+        let mut response = self.send(&self.endpoint(), &request).await?;
+
+        // Errors are sent as a normal JSON body, not as a stream
+        let status = response.status();
+        if !status.is_success() {
+            let text = response.text().await.unwrap_or_default();
+            return Err(api_error(status, &text));
+        }
+
+        let mut parser = SseParser::new();
+        let mut full = String::new();
+
+        while let Some(bytes) = tokio::time::timeout(self.read_timeout, response.chunk())
+            .await
+            .map_err(|_| format!("Stream timeout, no data for {}s", self.read_timeout.as_secs()))?
+            .map_err(|e| format!("Failed to read stream: {}", e))?
+        {
+            for data in parser.push(&bytes) {
+                if data == "[DONE]" {
+                    return Ok(full);
+                }
+
+                let chunk: OpenAIStreamChunk = serde_json::from_str(&data)
+                    .map_err(|e| format!("Failed to parse stream chunk: {} - Chunk: {}", e, data))?;
+
+                if let Some(err) = chunk.error {
+                    return Err(format!("OpenAI API error: {}", err.message));
+                }
+
+                // Sent in a last chunk without choices if include_usage is set
+                if let Some(usage) = chunk.usage {
+                    self.record_usage(&request.model, usage);
+                }
+
+                let content = chunk
+                    .choices
+                    .first()
+                    .and_then(|choice| choice.delta.as_ref())
+                    .and_then(|delta| delta.content.as_deref())
+                    .unwrap_or("");
+
+                if !content.is_empty() {
+                    full.push_str(content);
+                    on_chunk(content);
+                }
+            }
+        }
+
+        if full.is_empty() {
+            return Err("No response content in OpenAI stream".to_string());
+        }
+        Ok(full)
+    }
+}
+
+impl EmbeddingProvider for OpenAIClient {
+    fn embedding_model(&self) -> &str {
+        &self.embedding_model
+    }
+
+    async fn embed(&self, texts: Vec<String>) -> Result<Vec<Vec<f32>>, String> {
+        if texts.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let count = texts.len();
+        let request = OpenAIEmbeddingRequest {
+            model: self.embedding_model.clone(),
+            input: texts,
+        };
+        let response = self.send(&self.embeddings_endpoint(), &request).await?;
+
+        let status = response.status();
+        let text = tokio::time::timeout(self.read_timeout, response.text())
+            .await
+            .map_err(|_| format!("Timeout after {}s while reading the response", self.read_timeout.as_secs()))?
+            .map_err(|e| format!("Failed to get response text: {}", e))?;
+
+        if !status.is_success() {
+            return Err(api_error(status, &text));
+        }
+
+        let mut data: OpenAIEmbeddingResponse = serde_json::from_str(&text)
+            .map_err(|e| format!("Failed to parse embeddings: {} - Response: {}", e, text))?;
+
+        if let Some(err) = data.error {
+            return Err(format!("OpenAI API error: {}", err.message));
+        }
+        if let Some(usage) = data.usage {
+            self.record_usage(&request.model, usage);
+        }
+        if data.data.len() != count {
+            return Err(format!("Expected {} embeddings, got {}", count, data.data.len()));
+        }
+
+        data.data.sort_by_key(|embedding| embedding.index);
+        Ok(data.data.into_iter().map(|embedding| embedding.embedding).collect())
+    }
+}
+
+fn http_client(connect_timeout: Duration) -> reqwest::Client {
+    reqwest::Client::builder()
+        .connect_timeout(connect_timeout)
+        .build()
+        .unwrap_or_else(|_| reqwest::Client::new())
+}
+
+/// Error message of a failed request, the API sends errors as a JSON body
+fn api_error(status: reqwest::StatusCode, text: &str) -> String {
+    let message = serde_json::from_str::<OpenAIResponse>(text)
+        .ok()
+        .and_then(|data| data.error)
+        .map(|err| err.message)
+        .unwrap_or_else(|| text.to_string());
+    format!("OpenAI API error ({}): {}", status, message)
+}
+
+fn is_retryable(status: reqwest::StatusCode) -> bool {
+    status == reqwest::StatusCode::TOO_MANY_REQUESTS || status.is_server_error()
+}
+
+/// `Retry-After` in seconds, HTTP dates are ignored
+fn retry_after(response: &reqwest::Response) -> Option<Duration> {
+    response
+        .headers()
+        .get(reqwest::header::RETRY_AFTER)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.trim().parse::<u64>().ok())
+        .map(Duration::from_secs)
+}
+
+/// Random factor in [0, 1) for the backoff, good enough to spread retries of parallel chats
+fn jitter() -> f64 {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.subsec_nanos())
+        .unwrap_or(0);
+    (nanos % 1000) as f64 / 1000.0
+}
+
+/// Delay before retry number `attempt` (0-based): the server's `Retry-After`, otherwise
+/// exponential backoff with the upper half jittered, both capped at RETRY_MAX_DELAY
+fn backoff_delay(attempt: u32, retry_after: Option<Duration>, jitter: f64) -> Duration {
+    let delay = match retry_after {
+        Some(delay) => delay,
+        None => {
+            let exponential = RETRY_BASE_DELAY.saturating_mul(2u32.saturating_pow(attempt));
+            exponential.mul_f64(0.5 + jitter / 2.0)
+        }
+    };
+    delay.min(RETRY_MAX_DELAY)
+}
+
+/// The API only accepts names matching `^[a-zA-Z0-9_-]{1,64}$`
+fn sanitize_name(name: &str) -> String {
+    name.chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '_' || c == '-' { c } else { '_' })
+        .take(64)
+        .collect()
+}
+
+/// Parse extra headers in the form `Name: value; Other: value`
+fn parse_headers(headers: &str) -> Vec<(String, String)> {
+    headers
+        .split(';')
+        .filter_map(|header| {
+            let (name, value) = header.split_once(':')?;
+            let name = name.trim();
+            if name.is_empty() {
+                return None;
+            }
+            Some((name.to_string(), value.trim().to_string()))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lib::mock_server::{MockResponse, MockServer, MOCK_MODEL};
+    use crate::lib::provider::Role;
+
+    #[test]
+    fn test_endpoint() {
+        let client = OpenAIClient::for_base_url("http://localhost:8080/v1/", "llama");
+        assert_eq!(client.endpoint(), "http://localhost:8080/v1/chat/completions");
+        assert_eq!(client.model(), "llama");
+        assert_eq!(client.embeddings_endpoint(), "http://localhost:8080/v1/embeddings");
+        assert_eq!(client.embedding_model(), DEFAULT_EMBEDDING_MODEL);
+    }
+
+    #[test]
+    fn test_from_config() {
+        let config = DirConfig::parse(
+            "[chat]\nbase_url = \"http://localhost:11434/v1\"\nmodel = \"llama3\"\nread_timeout_secs = 5\nmax_retries = 0",
+        )
+        .unwrap();
+        let client = OpenAIClient::from_config(&config).unwrap();
+        assert_eq!(client.base_url(), "http://localhost:11434/v1");
+        assert_eq!(client.model(), "llama3");
+        assert_eq!(client.connect_timeout, DEFAULT_CONNECT_TIMEOUT);
+        assert_eq!(client.read_timeout, Duration::from_secs(5));
+        assert_eq!(client.max_retries, 0);
+    }
+
+    #[test]
+    fn test_build_request_with_options() {
+        let client = OpenAIClient::for_base_url("http://localhost:8080/v1", "llama");
+        let options = CompletionOptions {
+            system: Some("Antworte auf Deutsch.".to_string()),
+            model: None,
+            temperature: Some(0.2),
+            max_tokens: Some(100),
+        };
+        let messages = vec![LlmMessage::new(Role::User, "hallo").with_name("Max")];
+        let request = client.build_request(messages, options, false);
+        assert_eq!(request.model, "llama");
+        assert_eq!(request.temperature, 0.2);
+        assert_eq!(request.max_tokens, Some(100));
+        assert_eq!(request.messages[0].role, "system");
+        assert_eq!(request.messages[0].content.as_deref(), Some("Antworte auf Deutsch."));
+        assert_eq!(request.messages[1].role, "user");
+        assert_eq!(request.messages[1].name, Some("Max".to_string()));
+    }
+
+    #[test]
+    fn test_build_request_with_tool_messages() {
+        let client = OpenAIClient::for_base_url("http://localhost:8080/v1", "llama");
+        let call = ToolCall {
+            id: "call_1".to_string(),
+            name: "read_file".to_string(),
+            arguments: "{\"path\":\"a.txt\"}".to_string(),
+        };
+        let messages = vec![
+            LlmMessage::tool_calls("", vec![call]),
+            LlmMessage::tool_result("call_1", "Inhalt"),
+        ];
+        let request = client.build_request(messages, CompletionOptions::default(), false);
+        let json = serde_json::to_value(&request).unwrap();
+        assert_eq!(json["messages"][0]["content"], serde_json::Value::Null);
+        assert_eq!(json["messages"][0]["tool_calls"][0]["type"], "function");
+        assert_eq!(json["messages"][0]["tool_calls"][0]["function"]["name"], "read_file");
+        assert_eq!(json["messages"][1]["role"], "tool");
+        assert_eq!(json["messages"][1]["tool_call_id"], "call_1");
+        assert!(json.get("tools").is_none());
+    }
+
+    #[test]
+    fn test_stream_requests_usage() {
+        let client = OpenAIClient::for_base_url("http://localhost:8080/v1", "llama");
+        let messages = vec![LlmMessage::new(Role::User, "hallo")];
+        let json = serde_json::to_value(client.build_request(messages.clone(), CompletionOptions::default(), true)).unwrap();
+        assert_eq!(json["stream_options"]["include_usage"], true);
+        let json = serde_json::to_value(client.build_request(messages, CompletionOptions::default(), false)).unwrap();
+        assert!(json.get("stream_options").is_none());
+    }
+
+    #[test]
+    fn test_backoff_delay() {
+        assert_eq!(backoff_delay(0, None, 0.0), Duration::from_millis(250));
+        assert_eq!(backoff_delay(0, None, 1.0), Duration::from_millis(500));
+        assert_eq!(backoff_delay(2, None, 1.0), Duration::from_secs(2));
+        assert_eq!(backoff_delay(20, None, 1.0), RETRY_MAX_DELAY);
+        assert_eq!(backoff_delay(0, Some(Duration::from_secs(7)), 0.3), Duration::from_secs(7));
+        assert_eq!(backoff_delay(0, Some(Duration::from_secs(600)), 0.3), RETRY_MAX_DELAY);
+    }
+
+    #[test]
+    fn test_is_retryable() {
+        assert!(is_retryable(reqwest::StatusCode::TOO_MANY_REQUESTS));
+        assert!(is_retryable(reqwest::StatusCode::BAD_GATEWAY));
+        assert!(!is_retryable(reqwest::StatusCode::BAD_REQUEST));
+        assert!(!is_retryable(reqwest::StatusCode::UNAUTHORIZED));
+    }
+
+    #[test]
+    fn test_api_error() {
+        let status = reqwest::StatusCode::TOO_MANY_REQUESTS;
+        let body = r#"{"error": {"message": "Rate limit reached"}}"#;
+        assert_eq!(api_error(status, body), "OpenAI API error (429 Too Many Requests): Rate limit reached");
+        assert_eq!(api_error(status, "busy"), "OpenAI API error (429 Too Many Requests): busy");
+    }
+
+    #[test]
+    fn test_sanitize_name() {
+        assert_eq!(sanitize_name("Max Mustermann"), "Max_Mustermann");
+        assert_eq!(sanitize_name("mara-2"), "mara-2");
+        assert_eq!(sanitize_name(&"x".repeat(80)).len(), 64);
+    }
+
+    #[test]
+    fn test_sse_parser_split_chunks() {
+        let mut parser = SseParser::new();
+        assert!(parser.push(b"data: {\"a\":").is_empty());
+        assert_eq!(parser.push(b"1}\n\ndata: [DONE]\n"), vec!["{\"a\":1}", "[DONE]"]);
+    }
+
+    #[test]
+    fn test_sse_parser_ignores_other_fields() {
+        let mut parser = SseParser::new();
+        let payloads = parser.push(b": keep-alive\r\nevent: message\r\ndata: x\r\n\r\n");
+        assert_eq!(payloads, vec!["x"]);
+    }
+
+    #[test]
+    fn test_parse_headers() {
+        let headers = parse_headers("X-Title: mara; HTTP-Referer: http://localhost ;broken");
+        assert_eq!(headers, vec![
+            ("X-Title".to_string(), "mara".to_string()),
+            ("HTTP-Referer".to_string(), "http://localhost".to_string()),
+        ]);
+    }
+
+    fn run<T>(future: impl std::future::Future<Output = T>) -> T {
+        tokio::runtime::Runtime::new().unwrap().block_on(future)
+    }
+
+    fn question() -> Vec<LlmMessage> {
+        vec![LlmMessage::new(crate::Role::User, "Hallo?")]
+    }
+
+    #[test]
+    fn test_mock_reply_and_usage() {
+        let server = MockServer::start().with_response(MockResponse::Reply("Hallo zurück".to_string()));
+        let client = server.client();
+
+        let response = run(client.generate_response(question(), CompletionOptions::default()));
+        assert_eq!(response, Ok("Hallo zurück".to_string()));
+        assert_eq!(server.requests()[0]["messages"][0]["content"], "Hallo?");
+
+        let usage = client.usage()[MOCK_MODEL];
+        assert_eq!((usage.prompt_tokens, usage.completion_tokens, usage.requests), (10, 2, 1));
+    }
+
+    #[test]
+    fn test_mock_stream() {
+        let server = MockServer::start().with_response(MockResponse::Reply("eins zwei drei".to_string()));
+        let client = server.client();
+
+        let mut chunks = Vec::new();
+        let response = run(client.generate_response_stream(question(), CompletionOptions::default(), |chunk| {
+            chunks.push(chunk.to_string());
+        }));
+        assert_eq!(response, Ok("eins zwei drei".to_string()));
+        assert_eq!(chunks, vec!["eins ", "zwei ", "drei"]);
+        assert_eq!(client.usage()[MOCK_MODEL].completion_tokens, 3);
+    }
+
+    #[test]
+    fn test_mock_tool_calls() {
+        let call = ("read_file".to_string(), "{\"path\":\"notes.txt\"}".to_string());
+        let server = MockServer::start().with_response(MockResponse::ToolCalls(vec![call]));
+        let tool = ToolSpec {
+            name: "read_file".to_string(),
+            description: "Datei lesen".to_string(),
+            parameters: serde_json::json!({"type": "object"}),
+        };
+
+        let completion = run(server.client().generate_with_tools(question(), CompletionOptions::default(), vec![tool])).unwrap();
+        assert_eq!(completion.tool_calls[0].name, "read_file");
+        assert_eq!(completion.tool_calls[0].arguments, "{\"path\":\"notes.txt\"}");
+        assert_eq!(server.requests()[0]["tools"][0]["function"]["name"], "read_file");
+    }
+
+    #[test]
+    fn test_mock_rate_limit_is_retried() {
+        let server = MockServer::start()
+            .with_response(MockResponse::RateLimited(0))
+            .with_response(MockResponse::Error(503, "Überlastet".to_string()))
+            .with_response(MockResponse::Reply("endlich".to_string()));
+        let client = server.client().with_max_retries(2);
+
+        let response = run(client.generate_response(question(), CompletionOptions::default()));
+        assert_eq!(response, Ok("endlich".to_string()));
+        assert_eq!(server.requests().len(), 3);
+    }
+
+    #[test]
+    fn test_mock_invalid_request_is_not_retried() {
+        let server = MockServer::start().with_response(MockResponse::Reply("nie".to_string()));
+        let client = server.client().with_header("Ungültig\n", "x").with_max_retries(3);
+
+        let started = std::time::Instant::now();
+        let response = run(client.generate_response(question(), CompletionOptions::default()));
+        assert!(response.unwrap_err().contains("Request failed"));
+        assert!(started.elapsed() < RETRY_BASE_DELAY);
+        assert!(server.requests().is_empty());
+    }
+
+    #[test]
+    fn test_mock_errors() {
+        let server = MockServer::start()
+            .with_response(MockResponse::Error(401, "Invalid API key".to_string()))
+            .with_response(MockResponse::RateLimited(0));
+        let client = server.client().with_max_retries(0);
+
+        let response = run(client.generate_response(question(), CompletionOptions::default()));
+        assert!(response.unwrap_err().contains("Invalid API key"));
+        let response = run(client.generate_response(question(), CompletionOptions::default()));
+        assert!(response.unwrap_err().contains("Rate limit"));
+        assert_eq!(server.requests().len(), 2);
+    }
+
+    #[test]
+    fn test_mock_read_timeout() {
+        let slow = MockResponse::Delayed(Duration::from_secs(2), Box::new(MockResponse::Reply("zu spät".to_string())));
+        let server = MockServer::start().with_response(slow);
+        let client = server
+            .client()
+            .with_timeouts(DEFAULT_CONNECT_TIMEOUT, Duration::from_millis(200))
+            .with_max_retries(0);
+
+        let response = run(client.generate_response(question(), CompletionOptions::default()));
+        assert!(response.unwrap_err().contains("Timeout"));
     }
 }
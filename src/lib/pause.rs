@@ -0,0 +1,117 @@
+use super::events::EventKind;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+/// Name of the sentinel file that pauses processing for a watch root
+pub const PAUSE_SENTINEL: &str = ".mara-pause";
+
+/// PauseState struct - holds the events queued per watch root while it is paused
+#[derive(Debug, Default)]
+pub struct PauseState {
+    queued: HashMap<PathBuf, Vec<(PathBuf, EventKind)>>,
+}
+
+impl PauseState {
+    pub fn new() -> Self {
+        PauseState {
+            queued: HashMap::new(),
+        }
+    }
+
+    /// Check if the path is the sentinel file directly inside the given root
+    pub fn is_sentinel(root: &Path, path: &Path) -> bool {
+        path.parent() == Some(root)
+            && path.file_name().and_then(|n| n.to_str()) == Some(PAUSE_SENTINEL)
+    }
+
+    /// A root is paused as long as its sentinel file exists
+    pub fn is_paused(root: &Path) -> bool {
+        root.join(PAUSE_SENTINEL).exists()
+    }
+
+    /// Queue an event for a paused root, coalescing it with earlier events for the same path
+    pub fn queue(&mut self, root: &Path, path: PathBuf, event_kind: EventKind) {
+        let events = self.queued.entry(root.to_path_buf()).or_default();
+
+        let Some(index) = events.iter().position(|(p, _)| *p == path) else {
+            events.push((path, event_kind));
+            return;
+        };
+
+        let coalesced = match (events[index].1, event_kind) {
+            // Created and deleted again while paused: nothing happened
+            (EventKind::Create, EventKind::Delete) => None,
+            (EventKind::Create, _) => Some(EventKind::Create),
+            // Deleted and recreated: the file was changed
+            (EventKind::Delete, EventKind::Create) => Some(EventKind::Modify),
+            (_, kind) => Some(kind),
+        };
+
+        match coalesced {
+            Some(kind) => events[index].1 = kind,
+            None => {
+                events.remove(index);
+            }
+        }
+    }
+
+    /// Take all queued events of a root in the order they were first seen
+    pub fn take(&mut self, root: &Path) -> Vec<(PathBuf, EventKind)> {
+        self.queued.remove(root).unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_sentinel() {
+        let root = Path::new("/tmp/_mara");
+        assert!(PauseState::is_sentinel(root, &root.join(PAUSE_SENTINEL)));
+        assert!(!PauseState::is_sentinel(root, &root.join("a").join(PAUSE_SENTINEL)));
+        assert!(!PauseState::is_sentinel(root, &root.join("test.chat")));
+    }
+
+    #[test]
+    fn test_queue_coalesces_modifies() {
+        let root = Path::new("/tmp/_mara");
+        let mut state = PauseState::new();
+        state.queue(root, root.join("a.chat"), EventKind::Modify);
+        state.queue(root, root.join("b.todo"), EventKind::Create);
+        state.queue(root, root.join("a.chat"), EventKind::Modify);
+        state.queue(root, root.join("b.todo"), EventKind::Modify);
+
+        let events = state.take(root);
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0], (root.join("a.chat"), EventKind::Modify));
+        assert_eq!(events[1], (root.join("b.todo"), EventKind::Create));
+    }
+
+    #[test]
+    fn test_queue_create_then_delete() {
+        let root = Path::new("/tmp/_mara");
+        let mut state = PauseState::new();
+        state.queue(root, root.join("tmp.chat"), EventKind::Create);
+        state.queue(root, root.join("tmp.chat"), EventKind::Delete);
+        assert!(state.take(root).is_empty());
+    }
+
+    #[test]
+    fn test_queue_delete_then_create() {
+        let root = Path::new("/tmp/_mara");
+        let mut state = PauseState::new();
+        state.queue(root, root.join("a.chat"), EventKind::Delete);
+        state.queue(root, root.join("a.chat"), EventKind::Create);
+        assert_eq!(state.take(root), vec![(root.join("a.chat"), EventKind::Modify)]);
+    }
+
+    #[test]
+    fn test_take_clears_queue() {
+        let root = Path::new("/tmp/_mara");
+        let mut state = PauseState::new();
+        state.queue(root, root.join("a.chat"), EventKind::Modify);
+        assert_eq!(state.take(root).len(), 1);
+        assert!(state.take(root).is_empty());
+    }
+}
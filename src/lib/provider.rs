@@ -0,0 +1,192 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::future::Future;
+
+/// Role of a message in a completion request
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Role {
+    System,
+    User,
+    Assistant,
+    Tool,
+}
+
+impl Role {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::System => "system",
+            Role::User => "user",
+            Role::Assistant => "assistant",
+            Role::Tool => "tool",
+        }
+    }
+}
+
+/// ToolSpec struct - a function the model may call, `parameters` is a JSON schema
+#[derive(Debug, Clone, PartialEq)]
+pub struct ToolSpec {
+    pub name: String,
+    pub description: String,
+    pub parameters: Value,
+}
+
+/// ToolCall struct - a call of a tool requested by the model, `arguments` is JSON
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ToolCall {
+    pub id: String,
+    pub name: String,
+    pub arguments: String,
+}
+
+/// Completion struct - a reply that is either text or a request to call tools
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Completion {
+    pub content: String,
+    pub tool_calls: Vec<ToolCall>,
+}
+
+/// Usage struct - tokens used by completion requests as reported by the server
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+pub struct Usage {
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+    pub requests: u64,
+}
+
+impl Usage {
+    pub fn add(&mut self, other: Usage) {
+        self.prompt_tokens += other.prompt_tokens;
+        self.completion_tokens += other.completion_tokens;
+        self.requests += other.requests;
+    }
+
+    pub fn total_tokens(&self) -> u64 {
+        self.prompt_tokens + self.completion_tokens
+    }
+}
+
+/// LlmMessage struct - a message as it is sent to the provider
+#[derive(Debug, Clone, PartialEq)]
+pub struct LlmMessage {
+    pub role: Role,
+    pub name: Option<String>,
+    pub content: String,
+    pub tool_calls: Vec<ToolCall>,     // Tool-Aufrufe einer Assistant-Nachricht
+    pub tool_call_id: Option<String>,  // Aufruf, auf den eine Tool-Nachricht antwortet
+}
+
+impl LlmMessage {
+    pub fn new(role: Role, content: &str) -> Self {
+        LlmMessage {
+            role,
+            name: None,
+            content: content.to_string(),
+            tool_calls: Vec::new(),
+            tool_call_id: None,
+        }
+    }
+
+    /// Assistant message that requested tool calls
+    pub fn tool_calls(content: &str, tool_calls: Vec<ToolCall>) -> Self {
+        LlmMessage {
+            tool_calls,
+            ..LlmMessage::new(Role::Assistant, content)
+        }
+    }
+
+    /// Result of a tool call
+    pub fn tool_result(tool_call_id: &str, content: &str) -> Self {
+        LlmMessage {
+            tool_call_id: Some(tool_call_id.to_string()),
+            ..LlmMessage::new(Role::Tool, content)
+        }
+    }
+
+    pub fn with_name(mut self, name: &str) -> Self {
+        self.name = Some(name.to_string());
+        self
+    }
+}
+
+/// Options of a completion request, unset values use the provider's defaults
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct CompletionOptions {
+    pub system: Option<String>,
+    pub model: Option<String>,
+    pub temperature: Option<f32>,
+    pub max_tokens: Option<u32>,
+}
+
+impl CompletionOptions {
+    /// Combine with more specific options, set values of `other` win
+    pub fn merge(self, other: CompletionOptions) -> CompletionOptions {
+        CompletionOptions {
+            system: other.system.or(self.system),
+            model: other.model.or(self.model),
+            temperature: other.temperature.or(self.temperature),
+            max_tokens: other.max_tokens.or(self.max_tokens),
+        }
+    }
+}
+
+/// LlmProvider trait - a chat completion backend
+pub trait LlmProvider: Sync {
+    /// Model used for completions
+    fn model(&self) -> &str;
+
+    /// Tokens used by the requests of this provider so far, per model
+    /// Providers that don't report usage return nothing
+    fn usage(&self) -> BTreeMap<String, Usage> {
+        BTreeMap::new()
+    }
+
+    /// Generate the next reply for the given messages
+    fn generate_response(
+        &self,
+        messages: Vec<LlmMessage>,
+        options: CompletionOptions,
+    ) -> impl Future<Output = Result<String, String>> + Send;
+
+    /// Generate the next reply, the model may answer with calls of the given tools instead
+    /// Providers without tool support always answer with text
+    fn generate_with_tools(
+        &self,
+        messages: Vec<LlmMessage>,
+        options: CompletionOptions,
+        tools: Vec<ToolSpec>,
+    ) -> impl Future<Output = Result<Completion, String>> + Send {
+        let _ = tools;
+        async move {
+            let content = self.generate_response(messages, options).await?;
+            Ok(Completion {
+                content,
+                tool_calls: Vec::new(),
+            })
+        }
+    }
+
+    /// Generate the next reply, `on_chunk` is called with every piece of text as it arrives
+    /// Providers without streaming deliver the whole reply as a single chunk
+    fn generate_response_stream<F: FnMut(&str) + Send>(
+        &self,
+        messages: Vec<LlmMessage>,
+        options: CompletionOptions,
+        mut on_chunk: F,
+    ) -> impl Future<Output = Result<String, String>> + Send {
+        async move {
+            let response = self.generate_response(messages, options).await?;
+            on_chunk(&response);
+            Ok(response)
+        }
+    }
+}
+
+/// EmbeddingProvider trait - turns texts into vectors for retrieval
+pub trait EmbeddingProvider: Sync {
+    /// Model of the vectors, vectors of different models can't be compared
+    fn embedding_model(&self) -> &str;
+
+    /// One vector per text, in the same order
+    fn embed(&self, texts: Vec<String>) -> impl Future<Output = Result<Vec<Vec<f32>>, String>> + Send;
+}
@@ -0,0 +1,40 @@
+use super::provider::LlmMessage;
+
+/// Tokens every message costs in addition to its content (role, separators)
+const MESSAGE_OVERHEAD: usize = 4;
+
+/// Estimate the tokens of a text
+/// Roughly 4 characters per token for English and German text, good enough for budgeting
+pub fn estimate_tokens(text: &str) -> usize {
+    text.chars().count().div_ceil(4)
+}
+
+/// Estimate the tokens of a list of messages as they are sent to the provider
+pub fn estimate_messages(messages: &[LlmMessage]) -> usize {
+    messages
+        .iter()
+        .map(|msg| estimate_tokens(&msg.content) + MESSAGE_OVERHEAD)
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lib::provider::Role;
+
+    #[test]
+    fn test_estimate_tokens() {
+        assert_eq!(estimate_tokens(""), 0);
+        assert_eq!(estimate_tokens("hallo"), 2);
+        assert_eq!(estimate_tokens(&"a".repeat(400)), 100);
+    }
+
+    #[test]
+    fn test_estimate_messages() {
+        let messages = vec![
+            LlmMessage::new(Role::User, "abcd"),
+            LlmMessage::new(Role::Assistant, "abcdefgh"),
+        ];
+        assert_eq!(estimate_messages(&messages), 1 + 4 + 2 + 4);
+    }
+}
@@ -0,0 +1,172 @@
+use super::config::DirConfig;
+use super::provider::Usage;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Usage store in the watch root
+pub const USAGE_FILE: &str = ".mara-usage.json";
+
+/// UsageStore struct - running token totals per chat file and model
+///
+/// Costs come from the prices in the root's .mara.toml, in USD per million tokens:
+/// [prices]
+/// "gpt-4o" = [2.5, 10.0]      # prompt, completion
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub struct UsageStore {
+    #[serde(skip)]
+    path: PathBuf,
+    pub files: BTreeMap<String, BTreeMap<String, Usage>>,
+}
+
+impl UsageStore {
+    /// Load the store of a watch root, a missing or broken file starts empty
+    pub fn load(root: &Path) -> Self {
+        let path = root.join(USAGE_FILE);
+        let mut store: UsageStore = fs::read_to_string(&path)
+            .ok()
+            .and_then(|content| serde_json::from_str(&content).ok())
+            .unwrap_or_default();
+        store.path = path;
+        store
+    }
+
+    pub fn save(&self) -> Result<(), String> {
+        let content = serde_json::to_string_pretty(self).map_err(|e| e.to_string())?;
+        fs::write(&self.path, content)
+            .map_err(|e| format!("Failed to write {}: {}", self.path.display(), e))
+    }
+
+    /// Add the usage of a reply to the totals of `file`
+    pub fn record(&mut self, file: &str, usage: &BTreeMap<String, Usage>) {
+        let models = self.files.entry(file.to_string()).or_default();
+        for (model, usage) in usage {
+            models.entry(model.clone()).or_default().add(*usage);
+        }
+    }
+
+    /// Totals of a file over all models
+    pub fn file_total(&self, file: &str) -> Usage {
+        let mut total = Usage::default();
+        for usage in self.files.get(file).into_iter().flat_map(|models| models.values()) {
+            total.add(*usage);
+        }
+        total
+    }
+
+    /// Cost of a file in USD, None if a model has no price
+    pub fn file_cost(&self, file: &str, config: &DirConfig) -> Option<f64> {
+        self.files
+            .get(file)?
+            .iter()
+            .map(|(model, usage)| cost(config, model, usage))
+            .sum()
+    }
+
+    /// Files sorted by cost, files without prices by tokens after them
+    pub fn biggest_spenders(&self, config: &DirConfig) -> Vec<(String, Usage, Option<f64>)> {
+        let mut files: Vec<(String, Usage, Option<f64>)> = self
+            .files
+            .keys()
+            .map(|file| (file.clone(), self.file_total(file), self.file_cost(file, config)))
+            .collect();
+
+        files.sort_by(|a, b| {
+            b.2.unwrap_or(-1.0)
+                .total_cmp(&a.2.unwrap_or(-1.0))
+                .then(b.1.total_tokens().cmp(&a.1.total_tokens()))
+        });
+        files
+    }
+
+    /// Report of the `limit` biggest spenders for the CLI
+    pub fn report(&self, config: &DirConfig, limit: usize) -> String {
+        if self.files.is_empty() {
+            return "Noch keine Nutzung erfasst.".to_string();
+        }
+
+        let mut lines = vec![format!("{:>10} {:>10} {:>6} {:>10}  Datei", "Prompt", "Antwort", "Anfr.", "Kosten")];
+        for (file, usage, cost) in self.biggest_spenders(config).into_iter().take(limit) {
+            lines.push(format!(
+                "{:>10} {:>10} {:>6} {:>10}  {}",
+                usage.prompt_tokens,
+                usage.completion_tokens,
+                usage.requests,
+                format_cost(cost),
+                file
+            ));
+        }
+        lines.join("\n")
+    }
+}
+
+/// Price of a model in USD per million tokens: (prompt, completion)
+pub fn price(config: &DirConfig, model: &str) -> Option<(f64, f64)> {
+    let prices = config.get("prices", model)?.as_array()?;
+    let value = |v: &toml::Value| v.as_float().or_else(|| v.as_integer().map(|i| i as f64));
+    Some((value(prices.first()?)?, value(prices.get(1)?)?))
+}
+
+/// Cost of a usage in USD, None if the model has no price
+pub fn cost(config: &DirConfig, model: &str, usage: &Usage) -> Option<f64> {
+    let (prompt, completion) = price(config, model)?;
+    Some((usage.prompt_tokens as f64 * prompt + usage.completion_tokens as f64 * completion) / 1_000_000.0)
+}
+
+pub fn format_cost(cost: Option<f64>) -> String {
+    match cost {
+        Some(cost) => format!("${:.4}", cost),
+        None => "-".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn usage(prompt_tokens: u64, completion_tokens: u64) -> BTreeMap<String, Usage> {
+        let mut usage_map = BTreeMap::new();
+        usage_map.insert("gpt-4o".to_string(), Usage { prompt_tokens, completion_tokens, requests: 1 });
+        usage_map
+    }
+
+    #[test]
+    fn test_record_totals() {
+        let mut store = UsageStore::default();
+        store.record("a.chat", &usage(100, 20));
+        store.record("a.chat", &usage(200, 30));
+        assert_eq!(store.file_total("a.chat"), Usage { prompt_tokens: 300, completion_tokens: 50, requests: 2 });
+        assert_eq!(store.file_total("b.chat"), Usage::default());
+    }
+
+    #[test]
+    fn test_cost() {
+        let config = DirConfig::parse("[prices]\n\"gpt-4o\" = [2.5, 10]").unwrap();
+        let mut store = UsageStore::default();
+        store.record("a.chat", &usage(1_000_000, 100_000));
+        assert_eq!(store.file_cost("a.chat", &config), Some(3.5));
+        assert_eq!(store.file_cost("a.chat", &DirConfig::new()), None);
+    }
+
+    #[test]
+    fn test_biggest_spenders() {
+        let config = DirConfig::parse("[prices]\n\"gpt-4o\" = [1.0, 1.0]").unwrap();
+        let mut store = UsageStore::default();
+        store.record("klein.chat", &usage(10, 10));
+        store.record("gross.chat", &usage(1000, 1000));
+        let files: Vec<String> = store.biggest_spenders(&config).into_iter().map(|f| f.0).collect();
+        assert_eq!(files, vec!["gross.chat", "klein.chat"]);
+    }
+
+    #[test]
+    fn test_save_load() {
+        let root = std::env::temp_dir().join(format!("mara_usage_{}", std::process::id()));
+        fs::create_dir_all(&root).unwrap();
+        let mut store = UsageStore::load(&root);
+        store.record("a.chat", &usage(100, 20));
+        store.save().unwrap();
+        assert_eq!(UsageStore::load(&root).file_total("a.chat").prompt_tokens, 100);
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
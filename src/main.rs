@@ -1,15 +1,45 @@
 use std::fs;
-use mara_watch::{Manager, create_sync_a_to_b, create_sync_a_to_c, create_chat_processor};
+use std::path::Path;
+use mara_watch::{Manager, WatcherBackend, DirConfig, UsageStore, create_sync_a_to_b, create_sync_a_to_c, create_chat_processor};
 use mara_watch::processors::{create_command_processor, create_todo_processor, create_doku_processor};
+use mara_watch::processors::chat_processor::convert_chat;
+
+/// Files with the most token usage shown by `mara_watch usage`
+const USAGE_REPORT_LIMIT: usize = 10;
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
+    // `mara_watch usage [root]` prints the biggest spenders instead of watching
+    if std::env::args().nth(1).as_deref() == Some("usage") {
+        let root = std::env::args().nth(2).unwrap_or_else(|| "_mara".to_string());
+        let root_path = Path::new(&root);
+        let config = DirConfig::load(Some(root_path), root_path);
+        println!("{}", UsageStore::load(root_path).report(&config, USAGE_REPORT_LIMIT));
+        return Ok(());
+    }
+
+    // `mara_watch convert <file>` writes plan.chat as plan.chat.md and vice versa
+    if std::env::args().nth(1).as_deref() == Some("convert") {
+        let file = std::env::args().nth(2).ok_or("Aufruf: mara_watch convert <datei>")?;
+        let target = convert_chat(Path::new(&file))?;
+        println!("{}", target.display());
+        return Ok(());
+    }
+
+    // Watch root: first argument, defaults to _mara in the current directory
+    let root = std::env::args().nth(1).unwrap_or_else(|| "_mara".to_string());
+
     // Create directories
-    fs::create_dir_all("_mara/a")?;
-    fs::create_dir_all("_mara/b")?;
-    fs::create_dir_all("_mara/c")?;
+    let root_path = Path::new(&root);
+    fs::create_dir_all(root_path.join("a"))?;
+    fs::create_dir_all(root_path.join("b"))?;
+    fs::create_dir_all(root_path.join("c"))?;
+
+    // Watcher backend: MARA_WATCHER=poll for network and container filesystems
+    dotenv::dotenv().ok();
+    let backend = WatcherBackend::from_env()?;
 
     // Initialize manager
-    let mut manager = Manager::new();
+    let mut manager = Manager::new().watcher_backend(backend);
 
     // Register all processes and watch paths
     manager = manager
@@ -19,7 +49,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         .register_process(create_command_processor())
         .register_process(create_todo_processor())
         .register_process(create_doku_processor())
-        .watch_path("/Users/ba22036/RustroverProjects/mara_watch/_mara");
+        .watch_path(&root);
 
     // Run the manager
     manager.run()?;
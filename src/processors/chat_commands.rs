@@ -0,0 +1,157 @@
+use super::chat_format::{chat_stem, ChatStyle};
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+/// Suffix of the files written by `/export md`
+pub const EXPORT_EXTENSION: &str = ".export.md";
+
+/// ChatCommand enum - a slash command on the last lines of a user message
+///
+/// /model <name>   use another model from now on (front matter `model:`)
+/// /system <text>  set the system prompt (front matter `system:`)
+/// /summarize      collapse the chat into a summary now
+/// /clear          remove all messages, front matter and persona stay
+/// /export md      write the chat as markdown next to it (<name>.export.md)
+/// /retry          replace the last reply with a new one, the old one is kept as alternative
+/// /fork <name>    copy the chat up to here into <name>.chat next to it
+#[derive(Debug, Clone, PartialEq)]
+pub enum ChatCommand {
+    Model(String),
+    System(String),
+    Summarize,
+    Clear,
+    Export(String),
+    Retry,
+    Fork(String),
+}
+
+impl ChatCommand {
+    /// Parse a command line, unknown commands are None and stay normal text
+    pub fn parse(line: &str) -> Option<Self> {
+        let rest = line.trim().strip_prefix('/')?;
+        let (name, argument) = match rest.split_once(char::is_whitespace) {
+            Some((name, argument)) => (name, argument.trim()),
+            None => (rest, ""),
+        };
+
+        match (name, argument.is_empty()) {
+            ("model", false) => Some(ChatCommand::Model(argument.to_string())),
+            ("system", false) => Some(ChatCommand::System(argument.to_string())),
+            ("summarize", true) => Some(ChatCommand::Summarize),
+            ("clear", true) => Some(ChatCommand::Clear),
+            ("export", false) => Some(ChatCommand::Export(argument.to_string())),
+            ("retry", true) => Some(ChatCommand::Retry),
+            ("fork", false) => Some(ChatCommand::Fork(argument.to_string())),
+            _ => None,
+        }
+    }
+
+    /// Split the trailing command lines off a message, blank lines between them are dropped
+    pub fn split_trailing(content: &str) -> (String, Vec<ChatCommand>) {
+        let mut lines: Vec<&str> = content.lines().collect();
+        let mut commands = Vec::new();
+
+        while let Some(line) = lines.last() {
+            if line.trim().is_empty() {
+                lines.pop();
+                continue;
+            }
+            match ChatCommand::parse(line) {
+                Some(command) => {
+                    commands.insert(0, command);
+                    lines.pop();
+                }
+                None => break,
+            }
+        }
+
+        if commands.is_empty() {
+            return (content.to_string(), commands);
+        }
+        (lines.join("\n").trim_end().to_string(), commands)
+    }
+}
+
+impl fmt::Display for ChatCommand {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ChatCommand::Model(model) => write!(f, "/model {}", model),
+            ChatCommand::System(system) => write!(f, "/system {}", system),
+            ChatCommand::Summarize => write!(f, "/summarize"),
+            ChatCommand::Clear => write!(f, "/clear"),
+            ChatCommand::Export(format) => write!(f, "/export {}", format),
+            ChatCommand::Retry => write!(f, "/retry"),
+            ChatCommand::Fork(name) => write!(f, "/fork {}", name),
+        }
+    }
+}
+
+/// Path of a forked chat: `name` in the directory of the chat, with the chat's extension
+pub fn fork_path(chat_path: &Path, name: &str) -> Result<PathBuf, String> {
+    if name.contains('/') || name.contains('\\') || name.starts_with('.') {
+        return Err(format!("Ungültiger Name für den Fork: {}", name));
+    }
+
+    let style = ChatStyle::for_path(chat_path).unwrap_or_default();
+    let name = chat_stem(Path::new(name));
+    Ok(chat_path.with_file_name(format!("{}{}", name, style.extension())))
+}
+
+/// Path of a markdown export: plan.chat -> plan.export.md
+pub fn export_path(chat_path: &Path) -> PathBuf {
+    chat_path.with_file_name(format!("{}{}", chat_stem(chat_path), EXPORT_EXTENSION))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(ChatCommand::parse("/retry"), Some(ChatCommand::Retry));
+        assert_eq!(ChatCommand::parse("  /fork idee 2 "), Some(ChatCommand::Fork("idee 2".to_string())));
+        assert_eq!(ChatCommand::parse("/model gpt-4o"), Some(ChatCommand::Model("gpt-4o".to_string())));
+        assert_eq!(ChatCommand::parse("/system Sei knapp."), Some(ChatCommand::System("Sei knapp.".to_string())));
+        assert_eq!(ChatCommand::parse("/export md"), Some(ChatCommand::Export("md".to_string())));
+        assert_eq!(ChatCommand::parse("/fork"), None);
+        assert_eq!(ChatCommand::parse("/clear all"), None);
+        assert_eq!(ChatCommand::parse("/unbekannt"), None);
+        assert_eq!(ChatCommand::parse("retry"), None);
+    }
+
+    #[test]
+    fn test_display_round_trip() {
+        for line in ["/model gpt-4o", "/system Sei knapp.", "/summarize", "/clear", "/export md", "/retry", "/fork idee"] {
+            assert_eq!(ChatCommand::parse(line).unwrap().to_string(), line);
+        }
+    }
+
+    #[test]
+    fn test_split_trailing() {
+        let (content, commands) = ChatCommand::split_trailing("Frage\n/model gpt-4o\n\n/summarize");
+        assert_eq!(content, "Frage");
+        assert_eq!(commands, vec![ChatCommand::Model("gpt-4o".to_string()), ChatCommand::Summarize]);
+
+        // Only trailing lines count
+        let (content, commands) = ChatCommand::split_trailing("/clear\nWas bedeutet das?");
+        assert_eq!(content, "/clear\nWas bedeutet das?");
+        assert!(commands.is_empty());
+    }
+
+    #[test]
+    fn test_fork_path() {
+        let chat = Path::new("/w/chats/plan.chat");
+        assert_eq!(fork_path(chat, "idee"), Ok(PathBuf::from("/w/chats/idee.chat")));
+        assert_eq!(fork_path(chat, "idee.chat"), Ok(PathBuf::from("/w/chats/idee.chat")));
+        assert!(fork_path(chat, "../idee").is_err());
+        assert!(fork_path(chat, ".versteckt").is_err());
+        let markdown = Path::new("/w/chats/plan.chat.md");
+        assert_eq!(fork_path(markdown, "idee"), Ok(PathBuf::from("/w/chats/idee.chat.md")));
+    }
+
+    #[test]
+    fn test_export_path() {
+        assert_eq!(export_path(Path::new("/w/plan.chat")), PathBuf::from("/w/plan.export.md"));
+        assert_eq!(export_path(Path::new("/w/plan.chat.md")), PathBuf::from("/w/plan.export.md"));
+    }
+}
@@ -0,0 +1,448 @@
+use crate::{DirConfig, FrontMatter};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use super::persona::{Persona, ALTERNATIVE_PERSONA, DEFAULT_PERSONA, ERROR_PERSONA, SUMMARY_PERSONA, TOOL_PERSONA};
+
+/// Line between two messages
+pub const SEPARATOR: &str = "------";
+
+/// Start of a turn in the markdown style
+pub const HEADING: &str = "### ";
+
+/// Between persona and metadata in a markdown heading and between the metadata fields
+const META_SEPARATOR: &str = " · ";
+
+pub const CLASSIC_EXTENSION: &str = ".chat";
+pub const MARKDOWN_EXTENSION: &str = ".chat.md";
+
+/// ChatStyle enum - how a chat is written to its file
+///
+/// Classic (.chat):                            Markdown (.chat.md):
+/// User: [2026-10-18 14:10]                    ### User · 2026-10-18 14:10
+/// Hallo
+/// ------                                      Hallo
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum ChatStyle {
+    #[default]
+    Classic,
+    Markdown,
+}
+
+impl ChatStyle {
+    /// Style of a chat file by its name, None if it's no chat
+    pub fn for_path(path: &Path) -> Option<Self> {
+        let name = path.file_name()?.to_str()?;
+        if name.ends_with(MARKDOWN_EXTENSION) {
+            Some(ChatStyle::Markdown)
+        } else if name.ends_with(CLASSIC_EXTENSION) {
+            Some(ChatStyle::Classic)
+        } else {
+            None
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ChatStyle::Classic => CLASSIC_EXTENSION,
+            ChatStyle::Markdown => MARKDOWN_EXTENSION,
+        }
+    }
+
+    /// The other style, for conversions
+    pub fn other(&self) -> Self {
+        match self {
+            ChatStyle::Classic => ChatStyle::Markdown,
+            ChatStyle::Markdown => ChatStyle::Classic,
+        }
+    }
+
+    /// Line that ends (classic) or starts (markdown) a message
+    fn is_marker(&self, line: &str) -> bool {
+        match self {
+            ChatStyle::Classic => is_separator(line),
+            ChatStyle::Markdown => line.starts_with(HEADING),
+        }
+    }
+
+    /// A marker escaped with backslashes, e.g. `\------` is a literal `------` in a message
+    fn is_escaped_marker(&self, line: &str) -> bool {
+        let unescaped = line.trim_start().trim_start_matches('\\');
+        line.trim_start().starts_with('\\')
+            && match self {
+                ChatStyle::Classic => is_separator(unescaped),
+                ChatStyle::Markdown => unescaped.starts_with(HEADING),
+            }
+    }
+}
+
+/// Name of a chat file without its chat extension: plan.chat, plan.chat.md -> plan
+pub fn chat_stem(path: &Path) -> String {
+    let name = path.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default();
+    name.strip_suffix(MARKDOWN_EXTENSION)
+        .or_else(|| name.strip_suffix(CLASSIC_EXTENSION))
+        .unwrap_or(&name)
+        .to_string()
+}
+
+/// MessageMeta struct - optional metadata of a message, written next to its persona
+///
+/// mara: [2026-10-18 14:11 · gpt-4o · 812+95 tok · 2.4s]
+/// ### mara · 2026-10-18 14:11 · gpt-4o · 812+95 tok · 2.4s
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct MessageMeta {
+    pub timestamp: Option<String>,      // UTC, YYYY-MM-DD HH:MM
+    pub model: Option<String>,
+    pub tokens: Option<(u64, u64)>,     // Prompt, Antwort
+    pub latency: Option<Duration>,
+}
+
+impl MessageMeta {
+    pub fn is_empty(&self) -> bool {
+        *self == MessageMeta::default()
+    }
+
+    /// Parse the fields separated by `·`, None if one of them is not understood
+    /// A field that is no timestamp, token count or latency is the model
+    pub fn parse(text: &str) -> Option<Self> {
+        let mut meta = MessageMeta::default();
+        for field in text.split(META_SEPARATOR.trim()).map(|f| f.trim()).filter(|f| !f.is_empty()) {
+            if is_timestamp(field) && meta.timestamp.is_none() {
+                meta.timestamp = Some(field.to_string());
+            } else if let Some(tokens) = field.strip_suffix(" tok") {
+                let (prompt, completion) = tokens.split_once('+')?;
+                meta.tokens = Some((prompt.trim().parse().ok()?, completion.trim().parse().ok()?));
+            } else if let Some(secs) = field.strip_suffix('s').and_then(|s| s.parse::<f64>().ok()) {
+                meta.latency = Some(Duration::from_secs_f64(secs.max(0.0)));
+            } else if meta.model.is_none() && !field.contains(char::is_whitespace) {
+                meta.model = Some(field.to_string());
+            } else {
+                return None;
+            }
+        }
+        Some(meta)
+    }
+
+    /// Fields in a fixed order, empty if there is no metadata
+    pub fn render(&self) -> String {
+        let mut fields = Vec::new();
+        fields.extend(self.timestamp.clone());
+        fields.extend(self.model.clone());
+        fields.extend(self.tokens.map(|(prompt, completion)| format!("{}+{} tok", prompt, completion)));
+        fields.extend(self.latency.map(|latency| format!("{:.1}s", latency.as_secs_f64())));
+        fields.join(META_SEPARATOR)
+    }
+}
+
+/// YYYY-MM-DD HH:MM
+fn is_timestamp(text: &str) -> bool {
+    text.len() == 16
+        && text.char_indices().all(|(i, c)| match i {
+            4 | 7 => c == '-',
+            10 => c == ' ',
+            13 => c == ':',
+            _ => c.is_ascii_digit(),
+        })
+}
+
+/// ChatFormat struct - how persona header lines of a .chat file are recognised
+///
+/// Only `Name:` lines of known personas are headers, so a message can start with "Steps:".
+/// Known are the default persona, "User", the blocks written by mara, the declared personas
+/// and the personas of the chat header. Without strict mode a persona file in the
+/// personas directory makes a name known too, in strict mode it has to be declared.
+/// [chat]
+/// strict = true
+/// users = ["Alice"]
+/// assistants = ["mara"]
+///
+/// The front matter can do the same per chat (`strict: true`, `users: Alice, Bob`).
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct ChatFormat {
+    pub strict: bool,
+    pub personas: Vec<String>,
+    pub personas_dir: Option<PathBuf>,
+}
+
+impl ChatFormat {
+    pub fn from_config(config: &DirConfig) -> Self {
+        let mut format = ChatFormat {
+            strict: config.get_bool("chat", "strict").unwrap_or(false),
+            personas: Vec::new(),
+            personas_dir: None,
+        };
+        for key in ["users", "assistants"] {
+            if let Some(list) = config.get("chat", key).and_then(|v| v.as_array()) {
+                format.personas.extend(list.iter().filter_map(|v| v.as_str()).map(|p| p.to_string()));
+            }
+        }
+        format
+    }
+
+    /// Directory whose persona files are known personas outside of strict mode
+    pub fn with_personas_dir(mut self, dir: &Path) -> Self {
+        self.personas_dir = Some(dir.to_path_buf());
+        self
+    }
+
+    /// Add the settings of a chat's front matter and persona header
+    pub fn with_chat(mut self, front_matter: Option<&FrontMatter>, persona: Option<&str>) -> Self {
+        if let Some(front_matter) = front_matter {
+            if let Some(strict) = front_matter.get("strict") {
+                self.strict = strict == "true";
+            }
+            for key in ["users", "assistants"] {
+                if let Some(list) = front_matter.get(key) {
+                    self.personas.extend(list.split(',').map(|p| p.trim().to_string()).filter(|p| !p.is_empty()));
+                }
+            }
+        }
+        let names = persona.into_iter().flat_map(|p| p.split(',')).map(|p| p.trim().to_string());
+        self.personas.extend(names.filter(|p| !p.is_empty()));
+        self
+    }
+
+    /// Persona and metadata if `line` is a header line of the classic style
+    /// The metadata is in brackets after the colon: `mara: [2026-10-18 14:11 · gpt-4o]`
+    pub fn header(&self, line: &str) -> Option<(String, MessageMeta)> {
+        let line = line.trim();
+        if let Some(name) = line.strip_suffix(':') {
+            return Some((self.persona(name)?, MessageMeta::default()));
+        }
+
+        let (name, meta) = line.split_once(':')?;
+        let meta = meta.trim().strip_prefix('[')?.strip_suffix(']')?;
+        Some((self.persona(name)?, MessageMeta::parse(meta)?))
+    }
+
+    /// Persona and metadata if `line` is a heading of the markdown style
+    pub fn heading(&self, line: &str) -> Option<(String, MessageMeta)> {
+        let text = line.strip_prefix(HEADING)?.trim();
+        let (name, meta) = match text.split_once(META_SEPARATOR.trim()) {
+            Some((name, meta)) => (name, MessageMeta::parse(meta)?),
+            None => (text, MessageMeta::default()),
+        };
+        Some((self.persona(name)?, meta))
+    }
+
+    /// Checked persona name, it has to be known (see ChatFormat)
+    fn persona(&self, name: &str) -> Option<String> {
+        let name = name.trim();
+        if name.is_empty() {
+            return None;
+        }
+
+        let builtin = [DEFAULT_PERSONA, "User", "system", SUMMARY_PERSONA, TOOL_PERSONA, ERROR_PERSONA, ALTERNATIVE_PERSONA];
+        let declared = builtin.contains(&name) || self.personas.iter().any(|p| p == name);
+        let has_file = || {
+            !self.strict
+                && Persona::is_valid_name(name)
+                && self.personas_dir.as_ref().is_some_and(|dir| dir.join(format!("{}.md", name)).is_file())
+        };
+        (declared || has_file()).then(|| name.to_string())
+    }
+}
+
+/// A line of only dashes, at least as many as SEPARATOR, e.g. `------` or `----------`
+pub fn is_separator(line: &str) -> bool {
+    let line = line.trim();
+    line.len() >= SEPARATOR.len() && line.chars().all(|c| c == '-')
+}
+
+/// Header line of a message in the classic style
+pub fn header(persona: &str, meta: &MessageMeta) -> String {
+    if meta.is_empty() {
+        format!("{}:", persona)
+    } else {
+        format!("{}: [{}]", persona, meta.render())
+    }
+}
+
+/// Heading of a turn in the markdown style
+pub fn heading(persona: &str, meta: &MessageMeta) -> String {
+    if meta.is_empty() {
+        format!("{}{}", HEADING, persona)
+    } else {
+        format!("{}{}{}{}", HEADING, persona, META_SEPARATOR, meta.render())
+    }
+}
+
+/// Fence struct - tracks fenced code blocks (``` or ~~~), nothing inside of them is chat syntax
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Fence {
+    open: Option<char>,
+}
+
+impl Fence {
+    pub fn is_open(&self) -> bool {
+        self.open.is_some()
+    }
+
+    /// Feed the next line
+    pub fn update(&mut self, line: &str) {
+        let line = line.trim_start();
+        match self.open {
+            Some(marker) if line.starts_with(&marker.to_string().repeat(3)) => self.open = None,
+            Some(_) => {}
+            None => {
+                self.open = ["```", "~~~"]
+                    .iter()
+                    .find(|marker| line.starts_with(*marker))
+                    .and_then(|marker| marker.chars().next());
+            }
+        }
+    }
+
+    /// The closing line for a fence that is still open
+    pub fn closing(&self) -> Option<String> {
+        self.open.map(|marker| marker.to_string().repeat(3))
+    }
+}
+
+/// Content line as read from the file, one backslash of an escaped marker is removed
+pub fn unescape(line: &str, style: ChatStyle) -> String {
+    if style.is_escaped_marker(line) {
+        line.replacen('\\', "", 1)
+    } else {
+        line.to_string()
+    }
+}
+
+/// Content of a message as written to the file: markers outside of code blocks are
+/// escaped and a code block left open is closed, so the file parses back the same
+pub fn escape(content: &str, style: ChatStyle) -> String {
+    let mut fence = Fence::default();
+    let mut lines = Vec::new();
+
+    for line in content.lines() {
+        if !fence.is_open() && (style.is_marker(line) || style.is_escaped_marker(line)) {
+            let indent = &line[..line.len() - line.trim_start().len()];
+            lines.push(format!("{}\\{}", indent, line.trim_start()));
+        } else {
+            lines.push(line.to_string());
+        }
+        fence.update(line);
+    }
+
+    if let Some(closing) = fence.closing() {
+        lines.push(closing);
+    }
+    lines.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_header() {
+        let format = ChatFormat::default();
+        let plain = |name: &str| Some((name.to_string(), MessageMeta::default()));
+        assert_eq!(format.header("mara:"), plain("mara"));
+        assert_eq!(format.header("Steps:"), None);
+        assert_eq!(format.header("Max Mustermann:"), None);
+        let declared = ChatFormat { personas: vec!["Max Mustermann".to_string()], ..ChatFormat::default() };
+        assert_eq!(declared.header("Max Mustermann:"), plain("Max Mustermann"));
+        assert_eq!(format.header("Die folgenden Schritte sind wichtig:"), None);
+        assert_eq!(format.header("Siehe https://example.com:"), None);
+        assert_eq!(format.header(":"), None);
+        assert_eq!(format.header("Hinweis: [x] erledigt"), None);
+    }
+
+    #[test]
+    fn test_is_separator() {
+        assert!(is_separator("------"));
+        assert!(is_separator("  ----------  "));
+        assert!(!is_separator("-----"));
+        assert!(!is_separator("------ Ende"));
+        assert!(!is_separator("-------x"));
+    }
+
+    #[test]
+    fn test_strict_header() {
+        let format = ChatFormat { strict: true, personas: vec!["Alice".to_string()], ..ChatFormat::default() };
+        assert_eq!(format.header("Alice:").map(|h| h.0), Some("Alice".to_string()));
+        assert_eq!(format.header("mara:").map(|h| h.0), Some("mara".to_string()));
+        assert_eq!(format.header("Steps:"), None);
+    }
+
+    #[test]
+    fn test_header_of_persona_file() {
+        let dir = std::env::temp_dir().join(format!("mara_format_personas_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("critic.md"), "Du bist kritisch.").unwrap();
+
+        let format = ChatFormat::default().with_personas_dir(&dir);
+        assert_eq!(format.header("critic:").map(|h| h.0), Some("critic".to_string()));
+        assert_eq!(format.header("Steps:"), None);
+        let strict = ChatFormat { strict: true, ..format };
+        assert_eq!(strict.header("critic:"), None);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_with_chat() {
+        let (front_matter, _) = FrontMatter::split("---\nstrict: true\nusers: Alice, Bob\n---\n");
+        let format = ChatFormat::default().with_chat(front_matter.as_ref(), Some("critic"));
+        assert!(format.strict);
+        assert_eq!(format.personas, vec!["Alice", "Bob", "critic"]);
+    }
+
+    #[test]
+    fn test_escape_round_trip() {
+        let content = "Oben\n------\n\\------\nUnten";
+        let escaped = escape(content, ChatStyle::Classic);
+        assert_eq!(escaped, "Oben\n\\------\n\\\\------\nUnten");
+        let unescaped: Vec<String> = escaped.lines().map(|l| unescape(l, ChatStyle::Classic)).collect();
+        assert_eq!(unescaped.join("\n"), content);
+    }
+
+    #[test]
+    fn test_escape_leaves_code_blocks() {
+        assert_eq!(escape("```\n------\n```", ChatStyle::Classic), "```\n------\n```");
+        assert_eq!(escape("~~~sql\n------", ChatStyle::Classic), "~~~sql\n------\n~~~");
+    }
+
+    #[test]
+    fn test_escape_markdown() {
+        assert_eq!(escape("### Titel\n------", ChatStyle::Markdown), "\\### Titel\n------");
+        assert_eq!(unescape("\\### Titel", ChatStyle::Markdown), "### Titel");
+    }
+
+    #[test]
+    fn test_heading() {
+        let format = ChatFormat::default();
+        let meta = MessageMeta { timestamp: Some("2026-10-18 14:10".to_string()), ..MessageMeta::default() };
+        assert_eq!(heading("mara", &meta), "### mara · 2026-10-18 14:10");
+        assert_eq!(format.heading("### mara · 2026-10-18 14:10"), Some(("mara".to_string(), meta)));
+        assert_eq!(format.heading("### User"), Some(("User".to_string(), MessageMeta::default())));
+        assert_eq!(format.heading("#### mara"), None);
+    }
+
+    #[test]
+    fn test_message_meta() {
+        let meta = MessageMeta {
+            timestamp: Some("2026-10-18 14:11".to_string()),
+            model: Some("gpt-4o".to_string()),
+            tokens: Some((812, 95)),
+            latency: Some(Duration::from_millis(2400)),
+        };
+        assert_eq!(meta.render(), "2026-10-18 14:11 · gpt-4o · 812+95 tok · 2.4s");
+        assert_eq!(MessageMeta::parse(&meta.render()), Some(meta.clone()));
+        assert_eq!(header("mara", &meta), "mara: [2026-10-18 14:11 · gpt-4o · 812+95 tok · 2.4s]");
+        assert_eq!(ChatFormat::default().header(&header("mara", &meta)), Some(("mara".to_string(), meta)));
+
+        assert_eq!(MessageMeta::parse("llama3.1:8b").map(|m| m.model), Some(Some("llama3.1:8b".to_string())));
+        assert_eq!(MessageMeta::parse("gpt-4o · noch ein Modell"), None);
+        assert_eq!(MessageMeta::parse("viele+95 tok"), None);
+    }
+
+    #[test]
+    fn test_style_for_path() {
+        assert_eq!(ChatStyle::for_path(Path::new("a/plan.chat")), Some(ChatStyle::Classic));
+        assert_eq!(ChatStyle::for_path(Path::new("a/plan.chat.md")), Some(ChatStyle::Markdown));
+        assert_eq!(ChatStyle::for_path(Path::new("a/plan.md")), None);
+        assert_eq!(chat_stem(Path::new("a/plan.chat.md")), "plan");
+        assert_eq!(chat_stem(Path::new("a/plan.critic.chat")), "plan.critic");
+    }
+}
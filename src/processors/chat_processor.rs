@@ -1,28 +1,59 @@
-use crate::{FileEvent, EventOrigin, SyncProcess, OpenAIClient};
+use crate::{FileEvent, EventOrigin, SyncProcess, OpenAIClient, DirConfig, LlmProvider, ProgressWriter};
+use crate::{CachedProvider, CompletionOptions, FrontMatter, LlmMessage, ResponseCache, Role, Usage, UsageStore};
+use crate::{format_cost, now_timestamp};
+use crate::{estimate_messages, estimate_tokens};
+use super::includes::{IncludeLimits, Includes};
+use super::chat_commands::{export_path, fork_path, ChatCommand};
+use super::chat_format::{chat_stem, escape, header, heading, is_separator, unescape, ChatFormat, ChatStyle, Fence, MessageMeta};
+use super::persona::{Persona, RoleMap, ALTERNATIVE_PERSONA, ERROR_PERSONA, SUMMARY_PERSONA, TOOL_PERSONA};
+use super::rag::Retriever;
+use super::tools::ToolRegistry;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
 
 /// Message struct - represents a single message from a persona
 #[derive(Debug, Clone, PartialEq)]
 pub struct Message {
     pub persona: String,
     pub content: String,
+    pub commands: Vec<ChatCommand>,     // Slash-Befehle am Ende der Nachricht
+    pub meta: MessageMeta,              // Zeitpunkt, Modell, Tokens, Dauer
 }
 
 impl Message {
     pub fn new(persona: String, content: String) -> Self {
-        Message { persona, content }
+        Message { persona, content, commands: Vec::new(), meta: MessageMeta::default() }
+    }
+
+    pub fn with_meta(mut self, meta: MessageMeta) -> Self {
+        self.meta = meta;
+        self
     }
 }
 
 /// Chat struct - contains a list of messages
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Default)]
 pub struct Chat {
+    pub front_matter: Option<FrontMatter>,
+    pub persona: Option<String>,
     pub messages: Vec<Message>,
+    pub footer: Option<String>,     // Nutzungszeile, wird nicht mit eingelesen
+    pub style: ChatStyle,
 }
 
+/// Start of the usage footer line below the last message
+pub const USAGE_FOOTER_PREFIX: &str = "[usage]";
+
 impl Chat {
     pub fn new() -> Self {
         Chat {
+            front_matter: None,
+            persona: None,
             messages: Vec::new(),
+            footer: None,
+            style: ChatStyle::Classic,
         }
     }
 
@@ -30,126 +61,703 @@ impl Chat {
         self.messages.push(Message::new(persona, content));
     }
 
+    /// Remove the error blocks of failed replies, returns whether there were any
+    pub fn remove_errors(&mut self) -> bool {
+        let count = self.messages.len();
+        self.messages.retain(|msg| msg.persona != ERROR_PERSONA);
+        self.messages.len() != count
+    }
+
+    /// Take the slash commands of the last message if it's from a user
+    /// A message that only contained commands is dropped, commands of other personas stay
+    pub fn take_commands(&mut self, roles: &RoleMap) -> Vec<ChatCommand> {
+        let Some(last) = self.messages.last_mut() else {
+            return Vec::new();
+        };
+        if roles.role(&last.persona) != Role::User {
+            return Vec::new();
+        }
+
+        let commands = std::mem::take(&mut last.commands);
+        if last.content.is_empty() {
+            self.messages.pop();
+        }
+        commands
+    }
+
+    /// Set a front-matter option, the front matter is created if the chat has none
+    pub fn set_option(&mut self, key: &str, value: &str) {
+        self.front_matter.get_or_insert_with(FrontMatter::new).set(key, value);
+    }
+
+    /// The conversation as markdown, without alternatives and error blocks
+    pub fn to_markdown(&self, title: &str) -> String {
+        let mut markdown = format!("# {}\n", title);
+        for msg in &self.messages {
+            if msg.persona == ALTERNATIVE_PERSONA || msg.persona == ERROR_PERSONA {
+                continue;
+            }
+            markdown.push_str(&format!("\n## {}\n\n{}\n", msg.persona, msg.content));
+        }
+        markdown
+    }
+
+    /// Turn the last reply into an alternative so a new one is generated for the same turn
+    /// Returns false if there is no reply to replace
+    pub fn retry(&mut self, roles: &RoleMap) -> bool {
+        let Some(last) = self.messages.iter_mut().rev().find(|msg| msg.persona != ALTERNATIVE_PERSONA) else {
+            return false;
+        };
+        if roles.role(&last.persona) != Role::Assistant {
+            return false;
+        }
+
+        last.persona = ALTERNATIVE_PERSONA.to_string();
+        true
+    }
+
     /// Parse content into Chat
     /// Format: <persona>:\n<content>\n------
+    /// The header can carry metadata: `mara: [2026-10-18 14:11 · gpt-4o · 812+95 tok · 2.4s]`
     /// If content doesn't have a persona, it's treated as "User"
+    /// Only known personas are headers (see ChatFormat), a message may start with "Steps:"
+    /// An optional first line `persona: <name>` picks the AI persona that replies
+    /// With several names (`persona: mara, critic`) each one replies in turn
+    /// An optional front-matter block at the top sets request options:
+    /// ---
+    /// model: gpt-4o
+    /// temperature: 0.3
+    /// max_tokens: 500
+    /// system: Antworte in Stichpunkten.
+    /// ---
+    /// Fenced code blocks are part of the message as they are, `\------` is a literal separator
+    /// Slash commands on the last lines of the last message are parsed into its `commands`
     pub fn parse(content: &str) -> Self {
+        Self::parse_with(content, &ChatFormat::default())
+    }
+
+    /// Parse content with the header rules of a ChatFormat (e.g. strict mode from .mara.toml)
+    /// The style is detected: a first turn starting with `### ` means markdown
+    pub fn parse_with(content: &str, format: &ChatFormat) -> Self {
         let mut chat = Chat::new();
+        let (front_matter, content) = FrontMatter::split(content);
+        chat.front_matter = front_matter;
         let lines: Vec<&str> = content.lines().collect();
         let mut i = 0;
 
-        while i < lines.len() {
-            let line = lines[i].trim();
-
-            // Skip empty lines and separators
-            if line.is_empty() || line.starts_with("------") {
+        // Header line
+        while i < lines.len() && lines[i].trim().is_empty() {
+            i += 1;
+        }
+        if let Some(name) = lines.get(i).and_then(|line| line.trim().strip_prefix("persona:")) {
+            if !name.trim().is_empty() {
+                chat.persona = Some(name.trim().to_string());
                 i += 1;
-                continue;
             }
+        }
 
-            // Check if this line has a persona (ends with :)
-            if line.ends_with(':') {
-                let persona = line.trim_end_matches(':').to_string();
-                let mut content_lines = Vec::new();
-                i += 1;
-
-                // Collect content until separator or end
-                while i < lines.len() && !lines[i].trim().starts_with("------") {
-                    content_lines.push(lines[i]);
-                    i += 1;
-                }
+        let format = format
+            .clone()
+            .with_chat(chat.front_matter.as_ref(), chat.persona.as_deref());
+        let first_line = lines[i..].iter().find(|line| !line.trim().is_empty());
+        if first_line.is_some_and(|line| format.heading(line).is_some()) {
+            chat.style = ChatStyle::Markdown;
+        }
 
-                let msg_content = content_lines
-                    .join("\n")
-                    .trim()
-                    .to_string();
+        let mut current: Option<Message> = None;
+        let mut fence = Fence::default();
+        let last_line = lines.iter().rposition(|line| !line.trim().is_empty());
 
-                if !msg_content.is_empty() {
-                    chat.add_message(persona, msg_content);
+        for (index, line) in lines.iter().enumerate().skip(i) {
+            if !fence.is_open() {
+                // The usage footer is written where a message starts or at the end of the file,
+                // a `[usage]` line inside a message is part of it
+                let starts_message = current.as_ref().is_none_or(|msg| msg.content.trim().is_empty());
+                if line.trim().starts_with(USAGE_FOOTER_PREFIX) && (starts_message || Some(index) == last_line) {
+                    continue;
                 }
 
-                // Skip separator line
-                if i < lines.len() && lines[i].trim().starts_with("------") {
-                    i += 1;
+                match chat.style {
+                    ChatStyle::Classic if is_separator(line) => {
+                        chat.push_parsed(current.take());
+                        continue;
+                    }
+                    ChatStyle::Markdown => {
+                        if let Some((persona, meta)) = format.heading(line) {
+                            chat.push_parsed(current.take());
+                            current = Some(Message::new(persona, String::new()).with_meta(meta));
+                            continue;
+                        }
+                    }
+                    ChatStyle::Classic => {}
                 }
-            } else {
-                // No persona, treat as User message
-                let mut content_lines = vec![line];
-                i += 1;
 
-                // Collect until separator or end
-                while i < lines.len() && !lines[i].trim().starts_with("------") {
-                    content_lines.push(lines[i].trim());
-                    i += 1;
+                // Start of a message: skip blank lines, then a header
+                if current.is_none() {
+                    if line.trim().is_empty() {
+                        continue;
+                    }
+                    let header = format.header(line).filter(|_| chat.style == ChatStyle::Classic);
+                    if let Some((persona, meta)) = header {
+                        current = Some(Message::new(persona, String::new()).with_meta(meta));
+                        continue;
+                    }
+                    current = Some(Message::new("User".to_string(), String::new()));
                 }
+            }
+
+            if let Some(message) = current.as_mut() {
+                let line = if fence.is_open() { line.to_string() } else { unescape(line, chat.style) };
+                message.content.push_str(&line);
+                message.content.push('\n');
+            }
+            fence.update(line);
+        }
+        chat.push_parsed(current);
+
+        // Slash commands at the end of the last message
+        if let Some(last) = chat.messages.last_mut() {
+            let (content, commands) = ChatCommand::split_trailing(&last.content);
+            last.content = content;
+            last.commands = commands;
+        }
+
+        chat
+    }
+
+    /// Add a parsed message, leading and trailing blank lines are dropped, empty ones too
+    fn push_parsed(&mut self, message: Option<Message>) {
+        let Some(mut message) = message else {
+            return;
+        };
+
+        // Keep the indentation of the first line, e.g. of a code block
+        let lines: Vec<&str> = message.content.lines().collect();
+        message.content = match lines.iter().position(|line| !line.trim().is_empty()) {
+            Some(start) => lines[start..].join("\n").trim_end().to_string(),
+            None => String::new(),
+        };
+
+        if !message.content.is_empty() {
+            self.messages.push(message);
+        }
+    }
+
+    /// Content of a message with its commands, as written to the file
+    fn render_content(&self, msg: &Message) -> String {
+        std::iter::once(escape(&msg.content, self.style))
+            .filter(|c| !c.is_empty())
+            .chain(msg.commands.iter().map(|c| c.to_string()))
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    /// Render Chat back to content string, in its style
+    pub fn render(&self) -> String {
+        let mut preamble = match &self.front_matter {
+            Some(front_matter) => front_matter.render(),
+            None => String::new(),
+        };
+        if let Some(name) = &self.persona {
+            preamble.push_str(&format!("persona: {}\n", name));
+        }
 
-                let msg_content = content_lines.join("\n").trim().to_string();
-                if !msg_content.is_empty() {
-                    chat.add_message("User".to_string(), msg_content);
+        let footer = match &self.footer {
+            Some(footer) => format!("{} {}\n", USAGE_FOOTER_PREFIX, footer),
+            None => String::new(),
+        };
+
+        match self.style {
+            ChatStyle::Classic => {
+                preamble + &self.messages
+                    .iter()
+                    .map(|msg| format!("{}\n{}\n------\n", header(&msg.persona, &msg.meta), self.render_content(msg)))
+                    .collect::<Vec<_>>()
+                    .join("")
+                    + &footer
+            }
+            ChatStyle::Markdown => {
+                let mut blocks: Vec<String> = Vec::new();
+                if !preamble.is_empty() {
+                    blocks.push(preamble);
+                }
+                for msg in &self.messages {
+                    let title = heading(&msg.persona, &msg.meta);
+                    blocks.push(format!("{}\n\n{}\n", title, self.render_content(msg)));
                 }
 
-                // Skip separator line
-                if i < lines.len() && lines[i].trim().starts_with("------") {
-                    i += 1;
+                // Empty heading for the next user turn, dropped again when parsed
+                if self.messages.last().is_some_and(|msg| msg.persona != "User") {
+                    blocks.push(format!("{}\n", heading("User", &MessageMeta::default())));
+                }
+                if !footer.is_empty() {
+                    blocks.push(footer);
                 }
+                blocks.join("\n")
             }
         }
+    }
 
-        chat
+    /// Convert to another style, e.g. for `mara_watch convert`
+    pub fn with_style(mut self, style: ChatStyle) -> Self {
+        self.style = style;
+        self
     }
 
-    /// Render Chat back to content string
-    pub fn render(&self) -> String {
+    /// A reply is pending if the last message is from a user persona,
+    /// a reply of an assistant or a system block means it was answered already
+    pub fn reply_pending(&self, roles: &RoleMap) -> bool {
         self.messages
             .iter()
-            .map(|msg| format!("{}:\n{}\n------\n", msg.persona, msg.content))
-            .collect::<Vec<_>>()
-            .join("")
+            .rfind(|msg| msg.persona != ALTERNATIVE_PERSONA)
+            .map(|msg| roles.role(&msg.persona) == Role::User)
+            .unwrap_or(false)
+    }
+
+    /// AI personas of the header, `persona: mara, critic` lets both answer in turn
+    pub fn personas(&self) -> Vec<String> {
+        self.persona
+            .iter()
+            .flat_map(|header| header.split(','))
+            .map(|name| name.trim().to_string())
+            .filter(|name| !name.is_empty())
+            .collect()
+    }
+
+    /// The personas that still have to answer the last user turn, in the given order
+    /// `roles` has to know all of them as assistants, a trailing system block means
+    /// the turn was answered already
+    pub fn pending_personas(&self, personas: &[String], roles: &RoleMap) -> Vec<String> {
+        let mut replied: Vec<&String> = Vec::new();
+        let messages = self.messages.iter().rev().filter(|msg| msg.persona != ALTERNATIVE_PERSONA);
+        if messages.clone().next().is_some_and(|msg| roles.role(&msg.persona) == Role::System) {
+            return Vec::new();
+        }
+
+        for msg in messages {
+            match roles.role(&msg.persona) {
+                Role::User => {
+                    return personas.iter().filter(|p| !replied.contains(p)).cloned().collect();
+                }
+                Role::Assistant => replied.push(&msg.persona),
+                Role::System | Role::Tool => {}
+            }
+        }
+        Vec::new()
+    }
+
+    /// Index of the first message that is sent to the provider
+    /// Everything before the last summary block is covered by that summary
+    pub fn prompt_start(&self) -> usize {
+        self.messages
+            .iter()
+            .rposition(|msg| msg.persona == SUMMARY_PERSONA)
+            .unwrap_or(0)
+    }
+
+    /// Messages as they are sent to the provider
+    pub fn prompt_messages(&self, roles: &RoleMap) -> Vec<LlmMessage> {
+        self.messages[self.prompt_start()..]
+            .iter()
+            .filter(|msg| msg.persona != ALTERNATIVE_PERSONA)
+            .map(|msg| {
+                if msg.persona == SUMMARY_PERSONA {
+                    let content = format!("Zusammenfassung des bisherigen Gesprächs:\n{}", msg.content);
+                    roles.message(&msg.persona, &content)
+                } else {
+                    roles.message(&msg.persona, &msg.content)
+                }
+            })
+            .collect()
+    }
+
+    /// Find where older turns have to be cut off into a summary so the prompt fits into
+    /// `budget` tokens. The newest turns that fit into half of the budget are kept,
+    /// but at least the last one. None if the prompt fits or nothing can be summarized.
+    pub fn summary_cut(&self, roles: &RoleMap, budget: usize) -> Option<usize> {
+        if estimate_messages(&self.prompt_messages(roles)) <= budget {
+            return None;
+        }
+
+        let start = self.prompt_start();
+        let first_turn = if self.messages.get(start).map(|m| m.persona == SUMMARY_PERSONA).unwrap_or(false) {
+            start + 1
+        } else {
+            start
+        };
+
+        let mut cut = self.messages.len();
+        let mut kept = 0;
+        for (i, msg) in self.messages.iter().enumerate().skip(first_turn).rev() {
+            let tokens = estimate_messages(&[roles.message(&msg.persona, &msg.content)]);
+            if cut < self.messages.len() && kept + tokens > budget / 2 {
+                break;
+            }
+            kept += tokens;
+            cut = i;
+        }
+
+        if cut > first_turn {
+            Some(cut)
+        } else {
+            None
+        }
+    }
+
+    /// Request options set in the front matter
+    pub fn options(&self) -> CompletionOptions {
+        let Some(front_matter) = &self.front_matter else {
+            return CompletionOptions::default();
+        };
+
+        CompletionOptions {
+            system: front_matter.get("system").map(|s| s.to_string()),
+            model: front_matter.get("model").map(|m| m.to_string()),
+            temperature: front_matter.get("temperature").and_then(|t| t.parse().ok()),
+            max_tokens: front_matter.get("max_tokens").and_then(|t| t.parse().ok()),
+        }
     }
 }
 
-/// Generate mara response using OpenAI
-fn generate_mara_response(chat: &Chat) -> String {
-    // Prepare messages for OpenAI
-    let messages: Vec<(String, String)> = chat
-        .messages
+/// Context window assumed if neither .mara.toml nor the front matter set `context_tokens`
+const DEFAULT_CONTEXT_TOKENS: usize = 8000;
+
+/// Tokens kept free for the reply if `max_tokens` isn't set
+const DEFAULT_REPLY_TOKENS: usize = 1000;
+
+const SUMMARY_PROMPT: &str = "Fasse das folgende Gespräch knapp zusammen. \
+Behalte Namen, Fakten, Entscheidungen und offene Fragen. Antworte nur mit der Zusammenfassung.";
+
+/// Collapse older turns into a summary block if the prompt is too long for the context window
+/// The summary is inserted into the chat, so it's stored in the file and reused on later turns
+fn summarize_if_needed<P: LlmProvider>(
+    rt: &tokio::runtime::Runtime,
+    provider: &P,
+    chat: &mut Chat,
+    roles: &RoleMap,
+    options: &CompletionOptions,
+    context_tokens: usize,
+) {
+    let reserved = options.max_tokens.map(|t| t as usize).unwrap_or(DEFAULT_REPLY_TOKENS)
+        + options.system.as_deref().map(estimate_tokens).unwrap_or(0);
+    let budget = context_tokens.saturating_sub(reserved);
+
+    let Some(cut) = chat.summary_cut(roles, budget) else {
+        return;
+    };
+
+    // Previous summary and the turns after it, as one transcript
+    let transcript = chat.messages[chat.prompt_start()..cut]
         .iter()
-        .map(|msg| (msg.persona.clone(), msg.content.clone()))
+        .filter(|msg| msg.persona != ALTERNATIVE_PERSONA)
+        .map(|msg| format!("{}:\n{}\n", msg.persona, msg.content))
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    let summary_options = CompletionOptions {
+        system: Some(SUMMARY_PROMPT.to_string()),
+        model: options.model.clone(),
+        temperature: Some(0.2),
+        max_tokens: None,
+    };
+
+    match rt.block_on(provider.generate_response(vec![LlmMessage::new(Role::User, &transcript)], summary_options)) {
+        Ok(summary) => chat.messages.insert(cut, Message::new(SUMMARY_PERSONA.to_string(), summary.trim().to_string())),
+        Err(e) => eprintln!("Summary error, sending the full chat: {}", e),
+    }
+}
+
+/// Minimum time between two progressive writes of a streamed reply
+const STREAM_WRITE_INTERVAL: Duration = Duration::from_millis(200);
+
+/// Maximum number of tool call rounds before the model has to answer
+const MAX_TOOL_ROUNDS: usize = 5;
+
+/// Length of a tool result in the chat transcript, the model gets the full result
+const TOOL_TRANSCRIPT_CHARS: usize = 300;
+
+/// ReplyContext struct - everything besides the chat needed to generate a reply
+pub struct ReplyContext<'a> {
+    pub persona: &'a Persona,
+    pub roles: &'a RoleMap,
+    pub context_tokens: usize,
+    pub includes: Option<&'a Includes>,
+    pub tools: Option<&'a ToolRegistry>,
+    pub progress: Option<&'a ProgressWriter>,
+    pub retrieved: Option<&'a str>,     // Ausschnitte aus der Dokumentation
+}
+
+/// Transcript entry of a tool call, written into the chat as a `tool` message
+fn tool_transcript(name: &str, arguments: &str, result: &str) -> String {
+    let mut preview: String = result.chars().take(TOOL_TRANSCRIPT_CHARS).collect();
+    if preview.len() < result.len() {
+        preview.push_str(" ... (gekürzt)");
+    }
+    format!("{}({})\n=> {}", name, arguments, preview)
+}
+
+/// Let the model call tools until it answers with text
+/// Every call and its result is added to `chat` as a tool message
+fn run_tool_loop<P: LlmProvider>(
+    rt: &tokio::runtime::Runtime,
+    provider: &P,
+    chat: &mut Chat,
+    mut messages: Vec<LlmMessage>,
+    options: CompletionOptions,
+    tools: &ToolRegistry,
+    includes: &Includes,
+) -> Result<String, String> {
+    let specs = tools.specs();
+
+    for _ in 0..MAX_TOOL_ROUNDS {
+        let completion = rt.block_on(provider.generate_with_tools(messages.clone(), options.clone(), specs.clone()))?;
+        if completion.tool_calls.is_empty() {
+            return Ok(completion.content);
+        }
+
+        messages.push(LlmMessage::tool_calls(&completion.content, completion.tool_calls.clone()));
+        for call in &completion.tool_calls {
+            let result = tools.call(includes, call);
+            chat.add_message(TOOL_PERSONA.to_string(), tool_transcript(&call.name, &call.arguments, &result));
+            messages.push(LlmMessage::tool_result(&call.id, &result));
+        }
+    }
+
+    // Too many rounds, ask for an answer without offering tools
+    rt.block_on(provider.generate_response(messages, options))
+}
+
+/// Generate the reply of a persona with the given provider
+/// Options of the chat's front matter override the ones of the persona
+/// Long chats are summarized first, the summary block is added to `chat`
+/// Include directives are only expanded in the prompt, the chat keeps them
+/// With tools the model may act on the workspace first, tool calls are added to `chat`
+/// Otherwise, with a progress writer the reply is streamed into the chat file as it arrives
+fn generate_persona_response<P: LlmProvider>(
+    provider: &P,
+    chat: &mut Chat,
+    context: &ReplyContext,
+) -> Result<String, String> {
+    let persona = context.persona;
+    let options = persona.options().merge(chat.options());
+
+    // Use tokio runtime to execute async function
+    let rt = tokio::runtime::Runtime::new().unwrap();
+
+    summarize_if_needed(&rt, provider, chat, context.roles, &options, context.context_tokens);
+
+    // Prepare messages for the provider, @file and @glob directives of users are expanded
+    let messages: Vec<LlmMessage> = chat
+        .prompt_messages(context.roles)
+        .into_iter()
+        .map(|mut msg| {
+            if let (Role::User, Some(includes)) = (msg.role, context.includes) {
+                msg.content = includes.expand(&msg.content);
+            }
+            msg
+        })
         .collect();
 
-    // Try to create OpenAI client and get response
-    match OpenAIClient::new() {
-        Ok(client) => {
-            // Use tokio runtime to execute async function
-            let rt = tokio::runtime::Runtime::new().unwrap();
-            match rt.block_on(client.generate_response(messages)) {
-                Ok(response) => response,
-                Err(e) => {
-                    eprintln!("OpenAI error: {}", e);
-                    "Entschuldigung, ich konnte keine Antwort generieren.".to_string()
+    // Retrieved docs go right before the question
+    let mut messages = messages;
+    if let Some(retrieved) = context.retrieved {
+        let at = messages.len().saturating_sub(1);
+        messages.insert(at, LlmMessage::new(Role::System, retrieved));
+    }
+
+    match (context.tools, context.includes, context.progress) {
+        (Some(tools), Some(includes), _) => {
+            run_tool_loop(&rt, provider, chat, messages, options, tools, includes)
+        }
+        (_, _, Some(progress)) => {
+            let chat = &*chat;
+            let mut partial = String::new();
+            let mut last_write = Instant::now();
+
+            rt.block_on(provider.generate_response_stream(messages, options, |chunk| {
+                partial.push_str(chunk);
+                if last_write.elapsed() < STREAM_WRITE_INTERVAL {
+                    return;
+                }
+                last_write = Instant::now();
+
+                let mut streaming = chat.clone();
+                streaming.add_message(persona.name.clone(), partial.clone());
+                if let Err(e) = progress.write(streaming.render().as_bytes()) {
+                    eprintln!("Failed to write streamed reply: {}", e);
+                }
+            }))
+        }
+        _ => rt.block_on(provider.generate_response(messages, options)),
+    }
+}
+
+/// Generate the reply, or with `reply` false only summarize the chat if it's too long
+fn respond<P: LlmProvider>(
+    provider: &P,
+    chat: &mut Chat,
+    context: &ReplyContext,
+    reply: bool,
+) -> Result<Option<String>, String> {
+    if reply {
+        return generate_persona_response(provider, chat, context).map(Some);
+    }
+
+    let options = context.persona.options().merge(chat.options());
+    let rt = tokio::runtime::Runtime::new().unwrap();
+    summarize_if_needed(&rt, provider, chat, context.roles, &options, context.context_tokens);
+    Ok(None)
+}
+
+/// CommandEffects struct - what the slash commands of a turn ask the processor to do
+#[derive(Debug, Default, PartialEq)]
+struct CommandEffects {
+    summarize: bool,
+    retry: bool,
+    forked: bool,
+    exports: Vec<String>,
+}
+
+/// Apply slash commands to the chat state, in the order they were written
+/// Exports are done by the caller once the reply is in the chat
+fn apply_commands(chat: &mut Chat, commands: &[ChatCommand], roles: &RoleMap, path: &Path) -> CommandEffects {
+    let mut effects = CommandEffects::default();
+
+    for command in commands {
+        match command {
+            ChatCommand::Model(model) => chat.set_option("model", model),
+            ChatCommand::System(system) => chat.set_option("system", system),
+            ChatCommand::Summarize => effects.summarize = true,
+            ChatCommand::Clear => chat.messages.clear(),
+            ChatCommand::Export(format) => effects.exports.push(format.clone()),
+            ChatCommand::Retry => effects.retry |= chat.retry(roles),
+            ChatCommand::Fork(name) => {
+                effects.forked = true;
+                let forked = fork_path(path, name).and_then(|fork| {
+                    if fork.exists() {
+                        return Err(format!("Datei existiert bereits: {}", fork.display()));
+                    }
+                    fs::write(&fork, chat.render())
+                        .map_err(|e| format!("Fork konnte nicht geschrieben werden: {}", e))
+                });
+                if let Err(e) = forked {
+                    chat.add_message(ERROR_PERSONA.to_string(), e);
                 }
             }
         }
-        Err(e) => {
-            eprintln!("Failed to initialize OpenAI client: {}", e);
-            "Entschuldigung, OpenAI API Key ist nicht konfiguriert.".to_string()
+    }
+
+    effects
+}
+
+/// Write the requested exports of a chat, failures are added as error blocks
+fn export_chat(chat: &mut Chat, exports: &[String], path: &Path) {
+    for format in exports {
+        let result = match format.as_str() {
+            "md" | "markdown" => {
+                let title = chat_stem(path);
+                fs::write(export_path(path), chat.to_markdown(&title))
+                    .map_err(|e| format!("Export fehlgeschlagen: {}", e))
+            }
+            _ => Err(format!("Unbekanntes Exportformat: {}", format)),
+        };
+        if let Err(e) = result {
+            chat.add_message(ERROR_PERSONA.to_string(), e);
         }
     }
 }
 
+/// Error block added to the chat when no reply could be generated
+fn error_block(error: &str) -> String {
+    format!(
+        "Keine Antwort generiert: {}\n(Dieser Block wird beim nächsten Speichern entfernt und die Anfrage wiederholt, er kann auch gelöscht werden.)",
+        error
+    )
+}
+
+/// Record the usage of a reply in the root's UsageStore
+/// Returns the footer with the file's totals, tokens are shown with the cost if all models have a price
+fn record_usage(root: &Path, file: &str, usage: &BTreeMap<String, Usage>, config: &DirConfig) -> String {
+    let mut store = UsageStore::load(root);
+    store.record(file, usage);
+    if let Err(e) = store.save() {
+        eprintln!("Usage error: {}", e);
+    }
+
+    let total = store.file_total(file);
+    let mut footer = format!(
+        "{} Tokens ({} Prompt, {} Antwort) in {} Anfragen",
+        total.total_tokens(),
+        total.prompt_tokens,
+        total.completion_tokens,
+        total.requests
+    );
+    if let Some(cost) = store.file_cost(file, config) {
+        footer.push_str(&format!(", {}", format_cost(Some(cost))));
+    }
+    footer
+}
+
+/// Metadata of a generated reply, tokens are only known if the server reported them
+/// (a reply from the cache has none)
+fn reply_meta(model: String, usage: &BTreeMap<String, Usage>, latency: Duration) -> MessageMeta {
+    let mut total = Usage::default();
+    for model_usage in usage.values() {
+        total.add(*model_usage);
+    }
+
+    MessageMeta {
+        timestamp: Some(now_timestamp()),
+        model: Some(model).filter(|m| !m.is_empty()),
+        tokens: (total.requests > 0).then_some((total.prompt_tokens, total.completion_tokens)),
+        latency: Some(latency),
+    }
+}
+
+/// Convert a chat file to the other format: plan.chat <-> plan.chat.md
+/// The original is kept, an existing target is never overwritten
+/// Headers are recognised with the .mara.toml of the chat's directory, which is also the root
+/// of the persona files
+pub fn convert_chat(path: &Path) -> Result<PathBuf, String> {
+    let style = ChatStyle::for_path(path).ok_or_else(|| format!("Keine Chat-Datei: {}", path.display()))?;
+    let target = path.with_file_name(format!("{}{}", chat_stem(path), style.other().extension()));
+    if target.exists() {
+        return Err(format!("Ziel existiert bereits: {}", target.display()));
+    }
+
+    let content = fs::read_to_string(path).map_err(|e| format!("Lesen fehlgeschlagen: {}", e))?;
+    let dir = path.parent().unwrap_or(Path::new("."));
+    let config = DirConfig::load(None, dir);
+    let format = ChatFormat::from_config(&config).with_personas_dir(&Persona::dir(&config, Some(dir)));
+    let chat = Chat::parse_with(&content, &format).with_style(style.other());
+    fs::write(&target, chat.render()).map_err(|e| format!("Schreiben fehlgeschlagen: {}", e))?;
+    Ok(target)
+}
+
 /// Chat processor
-/// Filter: .chat files
+/// Filter: .chat and .chat.md files
 /// Target: same file
-/// Transform: parse chat, generate mara response with OpenAI, render back
+/// Transform: parse chat, generate a reply if the last message is from a user, render back
+///
+/// Settings in .mara.toml:
+/// [chat]
+/// model = "llama3"
+/// base_url = "http://localhost:11434/v1"
+/// stream = false          # write the reply at once instead of streaming it
+/// personas_dir = "personas"   # persona files, relative to the watch root
+/// context_tokens = 8000       # older turns are summarized above this size
+/// tools = true                # let the model read files, add todos and suggest commands
+/// connect_timeout_secs = 10
+/// read_timeout_secs = 60      # waiting for the response and between stream chunks
+/// max_retries = 3             # for 429, 5xx, timeouts and connection errors
+/// on_error = "block"          # "ignore" leaves the chat unchanged if the reply failed
+/// usage_footer = true         # show the tokens and cost of the file below the chat
+/// Matching parts of the docs are added to the prompt if [rag] is enabled (see Retriever)
 pub fn create_chat_processor() -> SyncProcess {
     SyncProcess::new(
         "Chat processor",
         |event: &FileEvent| {
-            let filename = event.path
-                .file_name()
-                .and_then(|n| n.to_str())
-                .map(|name| name.ends_with(".chat"))
-                .unwrap_or(false);
+            let filename = ChatStyle::for_path(&event.path).is_some();
 
             let right_origin = match &event.origin {
                 EventOrigin::External => true,
@@ -163,15 +771,188 @@ pub fn create_chat_processor() -> SyncProcess {
         |event: &FileEvent| {
             Some(event.path.clone())
         },
-        |_event, content| {
-            let content_str = String::from_utf8_lossy(&content);
+        |event, content| {
+            let content_str = String::from_utf8_lossy(content);
+
+            // Parse the chat, error blocks of earlier attempts are dropped and the reply retried
+            let personas_dir = Persona::dir(&event.config, event.root.as_deref());
+            let format = ChatFormat::from_config(&event.config).with_personas_dir(&personas_dir);
+            let mut chat = Chat::parse_with(&content_str, &format);
+            if let Some(style) = ChatStyle::for_path(&event.path) {
+                chat.style = style;
+            }
+            chat.remove_errors();
+
+            // Find the personas that reply, several answer in the order of the header
+            let personas: Vec<Persona> = match chat.personas().as_slice() {
+                [] => vec![Persona::resolve(&personas_dir, None, &event.path)],
+                names => names.iter().map(|name| Persona::resolve(&personas_dir, Some(name), &event.path)).collect(),
+            };
+            let names: Vec<String> = personas.iter().map(|p| p.name.clone()).collect();
+            let roles = RoleMap::new(&names[0])
+                .with_assistants(&names)
+                .with_declared(&event.config, chat.front_matter.as_ref());
+
+            // Slash commands of the last user turn change the chat, they are never sent
+            let commands = chat.take_commands(&roles);
+            let effects = apply_commands(&mut chat, &commands, &roles, &event.path);
+
+            // Only call the LLM if the last user turn still waits for answers, a fork
+            // leaves the reply to the forked chat
+            let pending = if effects.forked { Vec::new() } else { chat.pending_personas(&names, &roles) };
+            let reply = !pending.is_empty();
+            if !reply && !effects.summarize {
+                if commands.is_empty() {
+                    return Ok(content.to_vec());
+                }
+                export_chat(&mut chat, &effects.exports, &event.path);
+                return Ok(chat.render().into_bytes());
+            }
+
+            // The question is dated when it is answered
+            if let Some(last) = chat.messages.last_mut().filter(|msg| msg.meta.timestamp.is_none()) {
+                last.meta.timestamp = Some(now_timestamp());
+            }
+
+            // Generate and add the personas' messages using the configured provider
+            let progress = match event.config.get_bool("chat", "stream") {
+                Some(false) => None,
+                _ => event.progress.as_ref(),
+            };
+
+            // Token budget, front matter wins over .mara.toml, /summarize summarizes at once
+            let context_tokens = chat.front_matter
+                .as_ref()
+                .and_then(|f| f.get("context_tokens"))
+                .and_then(|t| t.parse().ok())
+                .or_else(|| event.config.get_int("chat", "context_tokens").map(|t| t as usize))
+                .unwrap_or(DEFAULT_CONTEXT_TOKENS);
+            let context_tokens = if effects.summarize { 0 } else { context_tokens };
+
+            // Includes are only allowed below a watch root
+            let includes = event.root
+                .as_deref()
+                .map(|root| Includes::new(root, IncludeLimits::from_config(&event.config)));
+
+            // Tools are opt-in, front matter wins over .mara.toml
+            let tools_enabled = chat.front_matter
+                .as_ref()
+                .and_then(|f| f.get("tools"))
+                .map(|t| t == "true")
+                .or_else(|| event.config.get_bool("chat", "tools"))
+                .unwrap_or(false);
+            let tools = tools_enabled.then(ToolRegistry::workspace);
+
+            // Parts of the docs that match the question, `rag: false` in the front matter opts out
+            let rag_enabled = chat.front_matter
+                .as_ref()
+                .and_then(|f| f.get("rag"))
+                .is_none_or(|r| r != "false");
+            let question = chat.messages
+                .iter()
+                .rfind(|msg| roles.role(&msg.persona) == Role::User)
+                .map(|msg| msg.content.clone())
+                .unwrap_or_default();
+            let retrieved = event.root
+                .as_deref()
+                .filter(|_| reply && rag_enabled)
+                .and_then(|root| Retriever::from_config(&event.config, root))
+                .and_then(|retriever| match retriever.context(&question) {
+                    Ok(retrieved) => retrieved,
+                    Err(e) => {
+                        eprintln!("[Chat processor] RAG error: {}", e);
+                        None
+                    }
+                });
+
+            // Without a pending turn the first persona only summarizes
+            let speakers: Vec<&Persona> = match reply {
+                true => personas.iter().filter(|p| pending.contains(&p.name)).collect(),
+                false => vec![&personas[0]],
+            };
+            let mut total_usage: BTreeMap<String, Usage> = BTreeMap::new();
+            let mut replied = false;
+            let mut error = None;
 
-            // Parse the chat
-            let mut chat = Chat::parse(&content_str);
+            // Every persona sees the replies before it, the others are users to it
+            for persona in speakers {
+                let persona_roles = RoleMap::new(&persona.name)
+                    .with_declared(&event.config, chat.front_matter.as_ref());
+                let context = ReplyContext {
+                    persona,
+                    roles: &persona_roles,
+                    context_tokens,
+                    includes: includes.as_ref(),
+                    tools: tools.as_ref(),
+                    progress,
+                    retrieved: retrieved.as_deref(),
+                };
 
-            // Generate and add mara message using OpenAI
-            let response = generate_mara_response(&chat);
-            chat.add_message("mara".to_string(), response);
+                // Repeated requests are answered from the cache below the watch root,
+                // except for /retry which asks for a different reply to the same prompt
+                let cache = event.root
+                    .as_deref()
+                    .filter(|_| !effects.retry)
+                    .and_then(|root| ResponseCache::from_config(&event.config, root));
+
+                let started = Instant::now();
+                let model = persona.options().merge(chat.options()).model;
+                let (response, usage, model) = match (OpenAIClient::from_config(&event.config), cache) {
+                    (Ok(provider), Some(cache)) => {
+                        let base_url = provider.base_url().to_string();
+                        let provider = CachedProvider::new(provider, cache).with_base_url(&base_url);
+                        let model = model.unwrap_or_else(|| provider.model().to_string());
+                        (respond(&provider, &mut chat, &context, reply), provider.usage(), model)
+                    }
+                    (Ok(provider), None) => {
+                        let model = model.unwrap_or_else(|| provider.model().to_string());
+                        (respond(&provider, &mut chat, &context, reply), provider.usage(), model)
+                    }
+                    (Err(e), _) => (Err(format!("OpenAI client not configured: {}", e)), BTreeMap::new(), String::new()),
+                };
+                let latency = started.elapsed();
+                for (model, usage) in &usage {
+                    total_usage.entry(model.clone()).or_default().add(*usage);
+                }
+
+                match response {
+                    Ok(Some(response)) => {
+                        let meta = reply_meta(model, &usage, latency);
+                        chat.messages.push(Message::new(persona.name.clone(), response).with_meta(meta));
+                        replied = true;
+                    }
+                    Ok(None) => {}
+                    Err(e) => {
+                        // The personas after a failed one wait for the next save
+                        eprintln!("[Chat processor] {} ({}): {}", event.path.display(), persona.name, e);
+                        error = Some(e);
+                        break;
+                    }
+                }
+            }
+
+            // Tokens are counted per file in the watch root, even for failed replies
+            if let Some(root) = event.root.as_deref() {
+                let footer = record_usage(root, &event.relative_path.to_string_lossy(), &total_usage, &event.config);
+                let show_footer = chat.front_matter
+                    .as_ref()
+                    .and_then(|f| f.get("usage_footer"))
+                    .map(|v| v == "true")
+                    .or_else(|| event.config.get_bool("chat", "usage_footer"))
+                    .unwrap_or(false);
+                chat.footer = show_footer.then_some(footer);
+            }
+
+            if let Some(e) = error {
+                // on_error = "ignore" leaves the chat as it was, apart from replies of earlier personas
+                if event.config.get_str("chat", "on_error") != Some("ignore") {
+                    chat.add_message(ERROR_PERSONA.to_string(), error_block(&e));
+                } else if !replied {
+                    return Ok(content.to_vec());
+                }
+            }
+
+            export_chat(&mut chat, &effects.exports, &event.path);
 
             // Render back
             let rendered = chat.render();
@@ -183,11 +964,19 @@ pub fn create_chat_processor() -> SyncProcess {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::lib::mock_server::{MockResponse, MockServer, MOCK_MODEL};
+    use crate::EventKind;
+
+    /// Only known personas start a message, Alice and Bob are declared
+    fn parse_declared(content: &str) -> Chat {
+        let format = ChatFormat { personas: vec!["Alice".to_string(), "Bob".to_string()], ..ChatFormat::default() };
+        Chat::parse_with(content, &format)
+    }
 
     #[test]
     fn test_parse_single_message() {
         let content = "Alice:\nHello world\n------\n";
-        let chat = Chat::parse(content);
+        let chat = parse_declared(content);
         assert_eq!(chat.messages.len(), 1);
         assert_eq!(chat.messages[0].persona, "Alice");
         assert_eq!(chat.messages[0].content, "Hello world");
@@ -205,7 +994,7 @@ mod tests {
     #[test]
     fn test_parse_user_antwort_message() {
         let content = "Alice:\nHello world\n------\nhallo";
-        let chat = Chat::parse(content);
+        let chat = parse_declared(content);
         assert_eq!(chat.messages.len(), 2);
         assert_eq!(chat.messages[0].persona, "Alice");
         assert_eq!(chat.messages[0].content, "Hello world");
@@ -216,7 +1005,7 @@ mod tests {
     #[test]
     fn test_parse_multiple_messages() {
         let content = "Alice:\nHello\n------\nBob:\nWorld\n------\n";
-        let chat = Chat::parse(content);
+        let chat = parse_declared(content);
         assert_eq!(chat.messages.len(), 2);
         assert_eq!(chat.messages[0].persona, "Alice");
         assert_eq!(chat.messages[0].content, "Hello");
@@ -236,7 +1025,7 @@ mod tests {
     #[test]
     fn test_round_trip() {
         let original = "Alice:\nHello world\n------\nBob:\nThis is a test\n------\n";
-        let chat = Chat::parse(original);
+        let chat = parse_declared(original);
         let rendered = chat.render();
         assert_eq!(rendered, original);
     }
@@ -244,12 +1033,112 @@ mod tests {
     #[test]
     fn test_multiline_content() {
         let content = "Alice:\nLine 1\nLine 2\n------\nBob:\nAnother text\n------\n";
-        let chat = Chat::parse(content);
+        let chat = parse_declared(content);
         assert_eq!(chat.messages.len(), 2);
         assert_eq!(chat.messages[0].content, "Line 1\nLine 2");
         assert_eq!(chat.messages[1].content, "Another text");
     }
 
+    #[test]
+    fn test_parse_persona_header() {
+        let content = "persona: critic\nUser:\nWas hältst du davon?\n------\n";
+        let chat = Chat::parse(content);
+        assert_eq!(chat.persona, Some("critic".to_string()));
+        assert_eq!(chat.messages.len(), 1);
+        assert_eq!(chat.messages[0].persona, "User");
+        assert_eq!(chat.render(), content);
+    }
+
+    #[test]
+    fn test_parse_front_matter() {
+        let content = "---\nmodel: gpt-4o\ntemperature: 0.3\nmax_tokens: 500\nsystem: Antworte kurz.\n---\nUser:\nhallo\n------\n";
+        let chat = Chat::parse(content);
+        assert_eq!(chat.messages.len(), 1);
+        assert_eq!(chat.messages[0].content, "hallo");
+
+        let options = chat.options();
+        assert_eq!(options.model, Some("gpt-4o".to_string()));
+        assert_eq!(options.temperature, Some(0.3));
+        assert_eq!(options.max_tokens, Some(500));
+        assert_eq!(options.system, Some("Antworte kurz.".to_string()));
+    }
+
+    #[test]
+    fn test_front_matter_round_trip() {
+        let original = "---\nmodel: gpt-4o\n---\npersona: critic\nUser:\nhallo\n------\n";
+        let chat = Chat::parse(original);
+        assert_eq!(chat.persona, Some("critic".to_string()));
+        assert_eq!(chat.render(), original);
+    }
+
+    #[test]
+    fn test_front_matter_overrides_persona() {
+        let chat = Chat::parse("---\ntemperature: 0.1\n---\nhallo");
+        let persona_options = CompletionOptions {
+            system: Some("Du bist mara.".to_string()),
+            model: Some("gpt-4".to_string()),
+            temperature: Some(0.7),
+            max_tokens: None,
+        };
+        let options = persona_options.merge(chat.options());
+        assert_eq!(options.system, Some("Du bist mara.".to_string()));
+        assert_eq!(options.model, Some("gpt-4".to_string()));
+        assert_eq!(options.temperature, Some(0.1));
+    }
+
+    #[test]
+    fn test_reply_pending() {
+        let roles = RoleMap::new("mara");
+        assert!(Chat::parse("hallo").reply_pending(&roles));
+        assert!(parse_declared("mara:\nHallo!\n------\nAlice:\nHi\n------\n").reply_pending(&roles));
+        assert!(!Chat::parse("User:\nhallo\n------\nmara:\nHallo!\n------\n").reply_pending(&roles));
+        assert!(!Chat::parse("User:\nhallo\n------\nsystem:\nSei kurz.\n------\n").reply_pending(&roles));
+        assert!(!Chat::parse("").reply_pending(&roles));
+    }
+
+    #[test]
+    fn test_prompt_starts_at_last_summary() {
+        let roles = RoleMap::new("mara");
+        let chat = Chat::parse("User:\nalt\n------\nsummary:\nMax hat gegrüßt.\n------\nUser:\nneu\n------\n");
+        assert_eq!(chat.prompt_start(), 1);
+
+        let messages = chat.prompt_messages(&roles);
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[0].role, Role::System);
+        assert!(messages[0].content.ends_with("Max hat gegrüßt."));
+        assert_eq!(messages[1].content, "neu");
+    }
+
+    #[test]
+    fn test_summary_cut() {
+        let roles = RoleMap::new("mara");
+        let mut chat = Chat::new();
+        for i in 0..10 {
+            chat.add_message("User".to_string(), format!("{} {}", i, "x".repeat(400)));
+            chat.add_message("mara".to_string(), "y".repeat(400));
+        }
+
+        // Everything fits
+        assert_eq!(chat.summary_cut(&roles, 10_000), None);
+
+        // Each message is ~105 tokens, half of 1000 keeps the newest 4
+        assert_eq!(chat.summary_cut(&roles, 1000), Some(16));
+
+        // A single huge message is always kept
+        assert_eq!(chat.summary_cut(&roles, 10), Some(19));
+    }
+
+    #[test]
+    fn test_summary_cut_after_summary() {
+        let roles = RoleMap::new("mara");
+        let mut chat = Chat::new();
+        chat.add_message("summary".to_string(), "z".repeat(400));
+        chat.add_message("User".to_string(), "x".repeat(400));
+
+        // Only the summary and the last message are left, nothing to summarize
+        assert_eq!(chat.summary_cut(&roles, 10), None);
+    }
+
     #[test]
     fn test_add_message() {
         let mut chat = Chat::new();
@@ -258,4 +1147,548 @@ mod tests {
         assert_eq!(chat.messages.len(), 2);
         assert_eq!(chat.messages[1].persona, "mara");
     }
+
+    /// Provider that first calls read_file, then answers with the tool result
+    struct ToolProvider;
+
+    impl LlmProvider for ToolProvider {
+        fn model(&self) -> &str {
+            "tool-test"
+        }
+
+        async fn generate_response(&self, _messages: Vec<LlmMessage>, _options: CompletionOptions) -> Result<String, String> {
+            Ok("ohne Tools".to_string())
+        }
+
+        async fn generate_with_tools(
+            &self,
+            messages: Vec<LlmMessage>,
+            _options: CompletionOptions,
+            tools: Vec<crate::ToolSpec>,
+        ) -> Result<crate::Completion, String> {
+            assert_eq!(tools.len(), 3);
+            let tool_calls = match messages.last() {
+                Some(msg) if msg.role == Role::Tool => Vec::new(),
+                _ => vec![crate::ToolCall {
+                    id: "call_1".to_string(),
+                    name: "read_file".to_string(),
+                    arguments: "{\"path\":\"notes.txt\"}".to_string(),
+                }],
+            };
+            let content = match messages.last() {
+                Some(msg) if msg.role == Role::Tool => format!("Gelesen: {}", msg.content),
+                _ => String::new(),
+            };
+            Ok(crate::Completion { content, tool_calls })
+        }
+    }
+
+    #[test]
+    fn test_tool_calls_in_transcript() {
+        let root = std::env::temp_dir().join(format!("mara_chat_tools_{}", std::process::id()));
+        std::fs::create_dir_all(&root).unwrap();
+        std::fs::write(root.join("notes.txt"), "Notizen").unwrap();
+        let includes = Includes::new(&root, IncludeLimits::default());
+        let tools = ToolRegistry::workspace();
+
+        let persona = Persona::new("mara");
+        let roles = RoleMap::new("mara");
+        let mut chat = Chat::parse("User:\nWas steht in notes.txt?");
+        let context = ReplyContext {
+            persona: &persona,
+            roles: &roles,
+            context_tokens: DEFAULT_CONTEXT_TOKENS,
+            includes: Some(&includes),
+            tools: Some(&tools),
+            progress: None,
+            retrieved: None,
+        };
+
+        let response = generate_persona_response(&ToolProvider, &mut chat, &context);
+        assert_eq!(response, Ok("Gelesen: Notizen".to_string()));
+        assert_eq!(chat.messages.len(), 2);
+        assert_eq!(chat.messages[1].persona, "tool");
+        assert_eq!(chat.messages[1].content, "read_file({\"path\":\"notes.txt\"})\n=> Notizen");
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_tool_transcript_is_truncated() {
+        let transcript = tool_transcript("read_file", "{}", &"x".repeat(400));
+        assert!(transcript.ends_with(" ... (gekürzt)"));
+        assert_eq!(transcript.matches('x').count(), TOOL_TRANSCRIPT_CHARS);
+    }
+
+    #[test]
+    fn test_remove_errors() {
+        let mut chat = Chat::parse("User:\nHallo\n------\nerror:\nKeine Antwort generiert: Timeout\n------\n");
+        let roles = RoleMap::new("mara");
+        assert!(chat.remove_errors());
+        assert_eq!(chat.messages.len(), 1);
+        assert!(chat.reply_pending(&roles));
+        assert!(!chat.remove_errors());
+    }
+
+    #[test]
+    fn test_usage_footer_is_not_a_message() {
+        let mut chat = Chat::parse("User:\nHallo\n------\nmara:\nHi\n------\n[usage] 30 Tokens (20 Prompt, 10 Antwort) in 1 Anfragen\n");
+        assert_eq!(chat.messages.len(), 2);
+        assert_eq!(chat.footer, None);
+        assert!(!chat.reply_pending(&RoleMap::new("mara")));
+
+        chat.footer = Some("30 Tokens".to_string());
+        assert!(chat.render().ends_with("------\n[usage] 30 Tokens\n"));
+    }
+
+    #[test]
+    fn test_usage_line_in_message_is_kept() {
+        let classic = Chat::parse("User:\nHallo\n[usage] zählt Tokens\nDanke\n------\n");
+        let markdown = Chat::parse("### User\n\nHallo\n[usage] zählt Tokens\nDanke\n\n### mara\n\nHi\n\n### User\n\n[usage] 10 Tokens\n");
+        assert_eq!(classic.messages[0].content, "Hallo\n[usage] zählt Tokens\nDanke");
+        assert_eq!(markdown.messages[0].content, classic.messages[0].content);
+        assert_eq!(markdown.messages.len(), 2);
+
+        // A question written below the footer or above it in the last turn
+        let classic = Chat::parse("User:\nHallo\n------\n[usage] 10 Tokens\nNoch eine Frage\n");
+        assert_eq!(classic.messages[1].content, "Noch eine Frage");
+        let markdown = Chat::parse("### User\n\nHallo\n\n### mara\n\nHi\n\n### User\n\nNoch eine Frage\n\n[usage] 10 Tokens\n");
+        assert_eq!(markdown.messages[2].content, "Noch eine Frage");
+    }
+
+    #[test]
+    fn test_take_commands() {
+        let roles = RoleMap::new("mara");
+        let mut chat = Chat::parse("User:\nHallo\n------\nmara:\nHi\n------\n/retry\n");
+        assert_eq!(chat.take_commands(&roles), vec![ChatCommand::Retry]);
+        assert_eq!(chat.messages.len(), 2);
+
+        let mut chat = Chat::parse("User:\nNoch eine Frage\n/fork frage\n");
+        assert_eq!(chat.take_commands(&roles), vec![ChatCommand::Fork("frage".to_string())]);
+        assert_eq!(chat.messages[0].content, "Noch eine Frage");
+
+        // Commands in a reply are not executed and stay in the file
+        let content = "User:\nHi\n------\nmara:\nSo geht es:\n/clear\n------\n";
+        let mut chat = Chat::parse(content);
+        assert!(chat.take_commands(&roles).is_empty());
+        assert_eq!(chat.render(), content);
+    }
+
+    #[test]
+    fn test_retry_keeps_alternative() {
+        let roles = RoleMap::new("mara");
+        let mut chat = Chat::parse("User:\nHallo\n------\nmara:\nAlte Antwort\n------\n");
+        assert!(chat.retry(&roles));
+        assert!(chat.reply_pending(&roles));
+        assert_eq!(chat.messages[1].persona, "alternative");
+        assert_eq!(chat.prompt_messages(&roles).len(), 1);
+
+        // The chat with the alternative survives a round trip
+        let parsed = Chat::parse(&chat.render());
+        assert_eq!(parsed, chat);
+        assert!(!Chat::parse("User:\nHallo\n").retry(&roles));
+    }
+
+    #[test]
+    fn test_apply_commands() {
+        let roles = RoleMap::new("mara");
+        let mut chat = Chat::parse("User:\nHallo\n------\nmara:\nHi\n------\n/model gpt-4o\n/system Sei knapp.\n/summarize\n/export md\n");
+        let commands = chat.take_commands(&roles);
+        assert_eq!(commands.len(), 4);
+
+        let effects = apply_commands(&mut chat, &commands, &roles, Path::new("/tmp/plan.chat"));
+        assert!(effects.summarize);
+        assert_eq!(effects.exports, vec!["md".to_string()]);
+        assert_eq!(chat.options().model, Some("gpt-4o".to_string()));
+        assert_eq!(chat.options().system, Some("Sei knapp.".to_string()));
+        assert_eq!(chat.messages.len(), 2);
+        assert!(!chat.reply_pending(&roles));
+    }
+
+    #[test]
+    fn test_clear_keeps_settings() {
+        let roles = RoleMap::new("mara");
+        let mut chat = Chat::parse("---\nmodel: llama3\n---\npersona: mara\nUser:\nHallo\n------\nmara:\nHi\n------\n/clear\n");
+        let commands = chat.take_commands(&roles);
+        apply_commands(&mut chat, &commands, &roles, Path::new("/tmp/plan.chat"));
+        assert_eq!(chat.render(), "---\nmodel: llama3\n---\npersona: mara\n");
+    }
+
+    #[test]
+    fn test_to_markdown() {
+        let chat = Chat::parse("User:\nHallo\n------\nalternative:\nAlt\n------\nmara:\nHi\n------\n");
+        assert_eq!(chat.to_markdown("plan"), "# plan\n\n## User\n\nHallo\n\n## mara\n\nHi\n");
+    }
+
+    #[test]
+    fn test_code_block_round_trip() {
+        let content = "User:\nWas macht das?\n```\nSteps:\n------\n  eingerückt\n```\n------\nmara:\nEine Tabelle:\n\n| a | b |\n|---|---|\n------\n";
+        let chat = Chat::parse(content);
+        assert_eq!(chat.messages.len(), 2);
+        assert_eq!(chat.messages[0].content, "Was macht das?\n```\nSteps:\n------\n  eingerückt\n```");
+        assert_eq!(chat.messages[1].content, "Eine Tabelle:\n\n| a | b |\n|---|---|");
+        assert_eq!(chat.render(), content);
+    }
+
+    #[test]
+    fn test_escaped_separator_round_trip() {
+        let mut chat = Chat::new();
+        chat.add_message("User".to_string(), "Oben\n------\nUnten".to_string());
+        let rendered = chat.render();
+        assert_eq!(rendered, "User:\nOben\n\\------\nUnten\n------\n");
+        assert_eq!(Chat::parse(&rendered), chat);
+    }
+
+    #[test]
+    fn test_sentence_is_not_a_persona() {
+        let chat = Chat::parse("mara:\nHi\n------\nIch habe folgende Frage:\nWie geht das?\n");
+        assert_eq!(chat.messages.len(), 2);
+        assert_eq!(chat.messages[1].persona, "User");
+        assert_eq!(chat.messages[1].content, "Ich habe folgende Frage:\nWie geht das?");
+    }
+
+    #[test]
+    fn test_strict_mode() {
+        let content = "---\nstrict: true\nusers: Alice\n---\nAlice:\nHallo\n------\nSteps:\n1. lesen\n------\n";
+        let chat = Chat::parse(content);
+        assert_eq!(chat.messages[0].persona, "Alice");
+        assert_eq!(chat.messages[1].persona, "User");
+        assert_eq!(chat.messages[1].content, "Steps:\n1. lesen");
+
+        let format = ChatFormat { strict: true, personas: Vec::new(), ..ChatFormat::default() };
+        let chat = Chat::parse_with("Steps:\n1. lesen\n", &format);
+        assert_eq!(chat.messages[0].persona, "User");
+    }
+
+    #[test]
+    fn test_open_code_block_is_closed() {
+        let mut chat = Chat::new();
+        chat.add_message("mara".to_string(), "```rust\nfn main() {".to_string());
+        chat.add_message("User".to_string(), "Danke".to_string());
+        let parsed = Chat::parse(&chat.render());
+        assert_eq!(parsed.messages.len(), 2);
+        assert_eq!(parsed.messages[0].content, "```rust\nfn main() {\n```");
+    }
+
+    #[test]
+    fn test_parse_markdown() {
+        let content = "### User · 2026-10-18 14:10\n\nHallo\n\n### mara · 2026-10-18 14:11\n\nHi\n\n```\n### kein Turn\n```\n\n### User\n";
+        let chat = Chat::parse(content);
+        assert_eq!(chat.style, ChatStyle::Markdown);
+        assert_eq!(chat.messages.len(), 2);
+        assert_eq!(chat.messages[0].meta.timestamp, Some("2026-10-18 14:10".to_string()));
+        assert_eq!(chat.messages[1].persona, "mara");
+        assert_eq!(chat.messages[1].content, "Hi\n\n```\n### kein Turn\n```");
+        assert_eq!(chat.render(), content);
+    }
+
+    #[test]
+    fn test_markdown_round_trip() {
+        let content = "---\nmodel: llama3\n---\npersona: mara\n\n### User\n\nHallo\n\\### Überschrift\n\n### mara\n\nHi\n\n### User\n\n[usage] 10 Tokens\n";
+        let chat = Chat::parse(content);
+        assert_eq!(chat.messages[0].content, "Hallo\n### Überschrift");
+        assert_eq!(chat.footer, None);
+
+        let mut chat = chat;
+        chat.footer = Some("10 Tokens".to_string());
+        assert_eq!(chat.render(), content);
+    }
+
+    #[test]
+    fn test_convert_styles() {
+        let classic = "User:\nHallo\n------\nmara:\nHi\n------\n";
+        let markdown = Chat::parse(classic).with_style(ChatStyle::Markdown).render();
+        assert_eq!(markdown, "### User\n\nHallo\n\n### mara\n\nHi\n\n### User\n");
+        assert_eq!(Chat::parse(&markdown).with_style(ChatStyle::Classic).render(), classic);
+    }
+
+    #[test]
+    fn test_convert_chat() {
+        let dir = std::env::temp_dir().join(format!("mara_convert_test_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("plan.chat");
+        fs::write(&path, "User:\nHallo\n------\n").unwrap();
+
+        let target = convert_chat(&path).unwrap();
+        assert_eq!(target, dir.join("plan.chat.md"));
+        assert_eq!(fs::read_to_string(&target).unwrap(), "### User\n\nHallo\n");
+        assert!(path.exists());
+        assert!(convert_chat(&path).is_err());
+        assert!(convert_chat(&dir.join("notiz.md")).is_err());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_meta_round_trip() {
+        let content = "User: [2026-10-18 14:10]\nHallo\n------\nmara: [2026-10-18 14:11 · gpt-4o · 812+95 tok · 2.4s]\nHi\n------\n";
+        let chat = Chat::parse(content);
+        assert_eq!(chat.messages[1].meta.model, Some("gpt-4o".to_string()));
+        assert_eq!(chat.messages[1].meta.tokens, Some((812, 95)));
+        assert_eq!(chat.messages[1].meta.latency, Some(Duration::from_millis(2400)));
+        assert_eq!(chat.render(), content);
+
+        let markdown = chat.with_style(ChatStyle::Markdown).render();
+        assert!(markdown.contains("### mara · 2026-10-18 14:11 · gpt-4o · 812+95 tok · 2.4s\n"));
+        assert_eq!(Chat::parse(&markdown).with_style(ChatStyle::Classic).render(), content);
+    }
+
+    #[test]
+    fn test_reply_meta() {
+        let mut usage = BTreeMap::new();
+        usage.insert("gpt-4o".to_string(), Usage { prompt_tokens: 800, completion_tokens: 90, requests: 1 });
+        usage.insert("gpt-4o-mini".to_string(), Usage { prompt_tokens: 12, completion_tokens: 5, requests: 1 });
+        let meta = reply_meta("gpt-4o".to_string(), &usage, Duration::from_millis(2400));
+        assert_eq!(meta.tokens, Some((812, 95)));
+        assert_eq!(meta.model, Some("gpt-4o".to_string()));
+        assert!(meta.timestamp.is_some());
+
+        let meta = reply_meta(String::new(), &BTreeMap::new(), Duration::ZERO);
+        assert_eq!(meta.tokens, None);
+        assert_eq!(meta.model, None);
+    }
+
+    /// Answers with the message before the question, where retrieved docs go
+    struct PromptProvider;
+
+    impl LlmProvider for PromptProvider {
+        fn model(&self) -> &str {
+            "prompt-test"
+        }
+
+        async fn generate_response(&self, messages: Vec<LlmMessage>, _options: CompletionOptions) -> Result<String, String> {
+            let before = messages.iter().rev().nth(1).ok_or("zu wenige Nachrichten")?;
+            Ok(format!("{}: {}", before.role.as_str(), before.content))
+        }
+    }
+
+    #[test]
+    fn test_retrieved_context_in_prompt() {
+        let persona = Persona::new("mara");
+        let roles = RoleMap::new("mara");
+        let mut chat = Chat::parse("User:\nHallo\n------\nmara:\nHi\n------\nUser:\nWie installiere ich das?");
+        let context = ReplyContext {
+            persona: &persona,
+            roles: &roles,
+            context_tokens: DEFAULT_CONTEXT_TOKENS,
+            includes: None,
+            tools: None,
+            progress: None,
+            retrieved: Some("[docs/setup.md]\ncargo install"),
+        };
+
+        let response = generate_persona_response(&PromptProvider, &mut chat, &context);
+        assert_eq!(response, Ok("system: [docs/setup.md]\ncargo install".to_string()));
+    }
+
+    /// Watch root whose .mara.toml points the chat at the mock server, the cache is off
+    fn mock_root(server: &MockServer, name: &str, chat_options: &str) -> (PathBuf, DirConfig) {
+        let root = std::env::temp_dir().join(format!("mara_chat_{}_{}", name, std::process::id()));
+        fs::create_dir_all(&root).unwrap();
+        let config = format!(
+            "[chat]\nbase_url = \"{}\"\nmodel = \"{}\"\n{}\n[cache]\nenabled = false\n",
+            server.base_url(),
+            MOCK_MODEL,
+            chat_options
+        );
+        (root, DirConfig::parse(&config).unwrap())
+    }
+
+    /// Save a chat file and run the chat processor on it like the manager does
+    fn save_chat(root: &Path, config: &DirConfig, file: &str, content: &str) -> String {
+        let path = root.join(file);
+        fs::write(&path, content).unwrap();
+        let event = FileEvent::new(path, EventKind::Modify)
+            .with_root(root)
+            .with_config(config.clone());
+        let output = create_chat_processor().transform_content(&event, content.as_bytes()).unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn test_mock_chat_reply() {
+        let server = MockServer::start().with_response(MockResponse::Reply("Hallo Max".to_string()));
+        let (root, config) = mock_root(&server, "reply", "");
+
+        let output = save_chat(&root, &config, "t.chat", "User:\nHallo\n------\n");
+        let chat = Chat::parse(&output);
+        assert_eq!(chat.messages.len(), 2);
+        assert_eq!(chat.messages[1].persona, "mara");
+        assert_eq!(chat.messages[1].content, "Hallo Max");
+        assert_eq!(chat.messages[1].meta.model, Some(MOCK_MODEL.to_string()));
+        assert_eq!(chat.messages[1].meta.tokens, Some((10, 2)));
+
+        let request = &server.requests()[0];
+        assert_eq!(request["model"], MOCK_MODEL);
+        assert_eq!(request["messages"][0]["content"], "Hallo");
+        assert_eq!(UsageStore::load(&root).file_total("t.chat").requests, 1);
+
+        // Answered chats are left alone
+        assert_eq!(save_chat(&root, &config, "t.chat", &output), output);
+        assert_eq!(server.requests().len(), 1);
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_mock_chat_stream_writes_progress() {
+        use std::sync::atomic::{AtomicBool, Ordering};
+        use std::sync::{Arc, Mutex};
+
+        let chunks = ["Eins ", "zwei ", "drei"].map(String::from).to_vec();
+        let server = MockServer::start().with_response(MockResponse::Streamed(chunks, Duration::from_millis(400)));
+        let (root, config) = mock_root(&server, "stream", "");
+        let path = root.join("t.chat");
+        let content = "User:\nZähl bis drei\n------\n";
+        fs::write(&path, content).unwrap();
+
+        // Record every state of the file while the reply is streamed into it
+        let done = Arc::new(AtomicBool::new(false));
+        let watcher = {
+            let (path, done) = (path.clone(), done.clone());
+            std::thread::spawn(move || {
+                let mut states: Vec<String> = Vec::new();
+                while !done.load(Ordering::SeqCst) {
+                    let state = fs::read_to_string(&path).unwrap_or_default();
+                    if states.last() != Some(&state) {
+                        states.push(state);
+                    }
+                    std::thread::sleep(Duration::from_millis(10));
+                }
+                states
+            })
+        };
+
+        let mappings = Arc::new(Mutex::new(Vec::new()));
+        let event = FileEvent::new(path.clone(), EventKind::Modify)
+            .with_root(&root)
+            .with_config(config)
+            .with_progress(ProgressWriter::new(&path, "Chat processor", mappings.clone()));
+        let output = create_chat_processor().transform_content(&event, content.as_bytes()).unwrap();
+        done.store(true, Ordering::SeqCst);
+        let states = watcher.join().unwrap();
+
+        let replies: Vec<String> = states
+            .iter()
+            .map(|state| Chat::parse(state))
+            .filter(|chat| chat.messages.len() == 2)
+            .map(|chat| chat.messages[1].content.clone())
+            .collect();
+        // Writes are throttled, which chunks are written depends on the timing
+        assert!(replies.contains(&"Eins zwei".to_string()), "{:?}", replies);
+        assert!(replies.iter().all(|reply| "Eins zwei drei".starts_with(reply.as_str())), "{:?}", replies);
+        assert_eq!(Chat::parse(&String::from_utf8(output).unwrap()).messages[1].content, "Eins zwei drei");
+
+        // The progressive writes count as internal, they don't trigger the processor again
+        let mappings = mappings.lock().unwrap();
+        assert_eq!(mappings.len(), 1);
+        assert_eq!(mappings[0].process_name, "Chat processor");
+        assert_eq!(server.requests()[0]["stream"], true);
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_mock_chat_error_block_is_retried() {
+        let server = MockServer::start()
+            .with_response(MockResponse::Error(400, "Kontext zu lang".to_string()))
+            .with_response(MockResponse::Reply("Jetzt klappt es".to_string()));
+        let (root, config) = mock_root(&server, "error", "max_retries = 0");
+
+        let output = save_chat(&root, &config, "t.chat", "User:\nHallo\n------\n");
+        let chat = Chat::parse(&output);
+        assert_eq!(chat.messages[1].persona, ERROR_PERSONA);
+        assert!(chat.messages[1].content.contains("Kontext zu lang"));
+
+        let output = save_chat(&root, &config, "t.chat", &output);
+        let chat = Chat::parse(&output);
+        assert_eq!(chat.messages.len(), 2);
+        assert_eq!(chat.messages[1].content, "Jetzt klappt es");
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_mock_chat_tools() {
+        let call = ("read_file".to_string(), "{\"path\":\"notes.txt\"}".to_string());
+        let server = MockServer::start()
+            .with_response(MockResponse::ToolCalls(vec![call]))
+            .with_response(MockResponse::Reply("In notes.txt steht: Einkaufen".to_string()));
+        let (root, config) = mock_root(&server, "tools", "tools = true");
+        fs::write(root.join("notes.txt"), "Einkaufen").unwrap();
+
+        let output = save_chat(&root, &config, "t.chat", "User:\nWas steht in notes.txt?\n------\n");
+        let chat = Chat::parse(&output);
+        let personas: Vec<&str> = chat.messages.iter().map(|m| m.persona.as_str()).collect();
+        assert_eq!(personas, vec!["User", TOOL_PERSONA, "mara"]);
+        assert!(chat.messages[1].content.contains("=> Einkaufen"));
+
+        // The tool result is sent back with the id of the call
+        let second = &server.requests()[1];
+        let tool_message = second["messages"].as_array().unwrap().last().unwrap().clone();
+        assert_eq!(tool_message["tool_call_id"], "call_0");
+        assert_eq!(tool_message["content"], "Einkaufen");
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_pending_personas() {
+        let names = vec!["mara".to_string(), "critic".to_string()];
+        let roles = RoleMap::new("mara").with_assistants(&names);
+        let format = ChatFormat { personas: names.clone(), ..ChatFormat::default() };
+        let pending = |content: &str| Chat::parse_with(content, &format).pending_personas(&names, &roles);
+
+        let chat = Chat::parse("persona: mara, critic\nUser:\nHallo\n------\n");
+        assert_eq!(chat.personas(), names);
+        assert_eq!(chat.pending_personas(&names, &roles), names);
+        assert_eq!(pending("User:\nHallo\n------\nmara:\nHi\n------\n"), vec!["critic"]);
+        assert_eq!(pending("User:\nHallo\n------\nmara:\nHi\n------\ntool:\nx\n------\ncritic:\nNa ja\n------\n"), Vec::<String>::new());
+        assert_eq!(pending("User:\nHallo\n------\nsystem:\nSei kurz.\n------\n"), Vec::<String>::new());
+        assert_eq!(pending("User:\nHallo\n------\nmara:\nHi\n------\nalternative:\nAlt\n------\n"), vec!["critic"]);
+        assert_eq!(pending(""), Vec::<String>::new());
+    }
+
+    #[test]
+    fn test_mock_chat_several_personas() {
+        let server = MockServer::start()
+            .with_response(MockResponse::Reply("Guter Plan".to_string()))
+            .with_response(MockResponse::Error(400, "Zu streng".to_string()))
+            .with_response(MockResponse::Reply("Der Plan hat Lücken".to_string()));
+        let (root, config) = mock_root(&server, "personas", "max_retries = 0");
+        fs::create_dir_all(root.join("personas")).unwrap();
+        fs::write(root.join("personas/critic.md"), "---\nmodel: critic-model\n---\nDu bist kritisch.").unwrap();
+
+        // The second persona fails, mara's reply is kept
+        let output = save_chat(&root, &config, "review.chat", "persona: mara, critic\nUser:\nMein Plan\n------\n");
+        let chat = Chat::parse(&output);
+        let personas: Vec<&str> = chat.messages.iter().map(|m| m.persona.as_str()).collect();
+        assert_eq!(personas, vec!["User", "mara", ERROR_PERSONA]);
+
+        // Only critic answers on the next save and sees mara's reply as a named user message
+        let output = save_chat(&root, &config, "review.chat", &output);
+        let chat = Chat::parse(&output);
+        let personas: Vec<&str> = chat.messages.iter().map(|m| m.persona.as_str()).collect();
+        assert_eq!(personas, vec!["User", "mara", "critic"]);
+        assert_eq!(chat.messages[2].meta.model, Some("critic-model".to_string()));
+
+        let request = &server.requests()[2];
+        assert_eq!(request["model"], "critic-model");
+        assert_eq!(request["messages"][0]["content"], "Du bist kritisch.");
+        assert_eq!(request["messages"][2]["role"], "user");
+        assert_eq!(request["messages"][2]["name"], "mara");
+        assert_eq!(request["messages"][2]["content"], "Guter Plan");
+        assert_eq!(server.requests().len(), 3);
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_mock_chat_personas_in_order() {
+        let server = MockServer::start()
+            .with_response(MockResponse::Reply("Erste Antwort".to_string()))
+            .with_response(MockResponse::Reply("Zweite Antwort".to_string()));
+        let (root, config) = mock_root(&server, "order", "");
+
+        let output = save_chat(&root, &config, "t.chat", "persona: critic, mara\nUser:\nFrage\n------\n");
+        let chat = Chat::parse(&output);
+        assert_eq!(chat.persona, Some("critic, mara".to_string()));
+        let replies: Vec<(&str, &str)> = chat.messages[1..].iter().map(|m| (m.persona.as_str(), m.content.as_str())).collect();
+        assert_eq!(replies, vec![("critic", "Erste Antwort"), ("mara", "Zweite Antwort")]);
+        fs::remove_dir_all(&root).unwrap();
+    }
 }
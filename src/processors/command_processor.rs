@@ -1,5 +1,19 @@
 use crate::{FileEvent, EventOrigin, SyncProcess};
-use std::process::Command;
+use std::io::Read;
+use std::path::Path;
+use std::process::{Child, Command, Stdio};
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Time a command may run if .mara.toml doesn't set `timeout_secs`
+pub const DEFAULT_COMMAND_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// How often a running command is checked
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// How long the output of a killed command is waited for, e.g. if a child left the group
+const OUTPUT_GRACE: Duration = Duration::from_secs(1);
 
 /// CommandEntry struct - represents a single command with its result
 #[derive(Debug, Clone, PartialEq)]
@@ -25,7 +39,7 @@ impl CommandEntry {
 }
 
 /// CommandLog struct - contains a list of command entries
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Default)]
 pub struct CommandLog {
     pub entries: Vec<CommandEntry>,
 }
@@ -113,7 +127,7 @@ impl CommandLog {
         self.entries
             .iter()
             .map(|entry| {
-                let result = entry.result.as_ref().map(|r| r.as_str()).unwrap_or("");
+                let result = entry.result.as_deref().unwrap_or("");
                 format!("{}\n------\n{}\n-----\n", entry.command, result)
             })
             .collect::<Vec<_>>()
@@ -121,39 +135,153 @@ impl CommandLog {
     }
 }
 
+/// Timeout of an entry from a `# timeout: 30s` line, units are ms, s, m and h
+/// A zero timeout is ignored like `timeout_secs = 0` in .mara.toml
+pub fn entry_timeout(command: &str) -> Option<Duration> {
+    command
+        .lines()
+        .filter_map(|line| line.trim().strip_prefix('#')?.trim().strip_prefix("timeout:"))
+        .find_map(|value| parse_duration(value.trim()))
+        .filter(|timeout| !timeout.is_zero())
+}
+
+/// `500ms`, `30s`, `2m`, `1h`, a plain number is seconds, None if it overflows
+fn parse_duration(value: &str) -> Option<Duration> {
+    let split = value.find(|c: char| !c.is_ascii_digit()).unwrap_or(value.len());
+    let (number, unit) = value.split_at(split);
+    let number: u64 = number.parse().ok()?;
+    match unit.trim() {
+        "ms" => Some(Duration::from_millis(number)),
+        "" | "s" => Some(Duration::from_secs(number)),
+        "m" | "min" => Some(Duration::from_secs(number.checked_mul(60)?)),
+        "h" => Some(Duration::from_secs(number.checked_mul(3600)?)),
+        _ => None,
+    }
+}
+
+/// Result line of a command that was killed, whole seconds as `30s`,
+/// a timeout like `# timeout: 500ms` as `500ms`
+fn timed_out(timeout: Duration) -> String {
+    if timeout.subsec_millis() == 0 {
+        format!("timed out after {}s", timeout.as_secs())
+    } else {
+        format!("timed out after {}ms", timeout.as_millis())
+    }
+}
+
+/// Kill the command with everything it started, it runs in its own process group
+fn kill_group(child: &mut Child) {
+    #[cfg(unix)]
+    unsafe {
+        libc::kill(-(child.id() as libc::pid_t), libc::SIGKILL);
+    }
+    let _ = child.kill();
+    let _ = child.wait();
+}
+
+/// Read a pipe to its end on a thread, the bytes are sent when it's closed
+fn read_pipe<R: Read + Send + 'static>(pipe: Option<R>) -> mpsc::Receiver<Vec<u8>> {
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        let mut bytes = Vec::new();
+        if let Some(mut pipe) = pipe {
+            let _ = pipe.read_to_end(&mut bytes);
+        }
+        let _ = sender.send(bytes);
+    });
+    receiver
+}
+
 /// Execute a command and return the output
-fn execute_command(command: &str) -> String {
+/// If `working_dir` is given, the command runs inside that directory
+/// A command running longer than `timeout` is killed with its process group, the
+/// output so far is kept and followed by "timed out after 30s" (see `timed_out`).
+/// This includes background children still holding the output open after the shell exited
+fn execute_command(command: &str, working_dir: Option<&Path>, timeout: Duration) -> String {
     // Use shell to execute the command
-    let output = if cfg!(target_os = "windows") {
-        Command::new("cmd")
-            .args(&["/C", command])
-            .output()
+    let mut shell = if cfg!(target_os = "windows") {
+        let mut shell = Command::new("cmd");
+        shell.args(["/C", command]);
+        shell
     } else {
-        Command::new("sh")
-            .arg("-c")
-            .arg(command)
-            .output()
+        let mut shell = Command::new("sh");
+        shell.arg("-c").arg(command);
+        shell
     };
 
-    match output {
-        Ok(output) => {
-            let stdout = String::from_utf8_lossy(&output.stdout);
-            let stderr = String::from_utf8_lossy(&output.stderr);
+    if let Some(dir) = working_dir {
+        shell.current_dir(dir);
+    }
+
+    #[cfg(unix)]
+    std::os::unix::process::CommandExt::process_group(&mut shell, 0);
 
-            if !stderr.is_empty() {
-                format!("{}{}", stdout, stderr)
-            } else {
-                stdout.to_string()
+    let mut child = match shell.stdin(Stdio::null()).stdout(Stdio::piped()).stderr(Stdio::piped()).spawn() {
+        Ok(child) => child,
+        Err(e) => return format!("Error executing command: {}", e),
+    };
+    let stdout = read_pipe(child.stdout.take());
+    let stderr = read_pipe(child.stderr.take());
+
+    // Wait for the command, the manager is blocked meanwhile
+    let started = Instant::now();
+    let mut killed = false;
+    loop {
+        match child.try_wait() {
+            Ok(Some(_)) => break,
+            Ok(None) if started.elapsed() >= timeout => {
+                kill_group(&mut child);
+                killed = true;
+                break;
             }
+            Ok(None) => thread::sleep(POLL_INTERVAL),
+            Err(e) => return format!("Error executing command: {}", e),
         }
-        Err(e) => format!("Error executing command: {}", e),
     }
+
+    // A background child (`sleep 100 &`) keeps the pipes open, its output is waited
+    // for only until the timeout and then it's killed with the group.
+    // A timeout too big for an Instant never runs out
+    let deadline = started.checked_add(timeout);
+    let mut output = String::new();
+    for pipe in [stdout, stderr] {
+        let wait = match (killed, deadline) {
+            (true, _) => OUTPUT_GRACE,
+            (false, Some(deadline)) => deadline.saturating_duration_since(Instant::now()),
+            (false, None) => Duration::MAX,
+        };
+        let bytes = match pipe.recv_timeout(wait) {
+            Ok(bytes) => bytes,
+            Err(_) if !killed => {
+                kill_group(&mut child);
+                killed = true;
+                pipe.recv_timeout(OUTPUT_GRACE).unwrap_or_default()
+            }
+            Err(_) => Vec::new(),
+        };
+        output.push_str(&String::from_utf8_lossy(&bytes));
+    }
+
+    if killed {
+        if !output.is_empty() && !output.ends_with('\n') {
+            output.push('\n');
+        }
+        output.push_str(&timed_out(timeout));
+    }
+    output
 }
 
 /// Command processor
 /// Filter: .command files
 /// Target: same file
 /// Transform: parse commands, execute new ones, render back
+///
+/// Settings in .mara.toml:
+/// [command]
+/// enabled = false        # don't execute anything in this directory
+/// working_dir = "."      # run commands relative to the .command file instead of the manager
+/// timeout_secs = 60      # kill commands running longer, an entry can override it:
+///                        # `# timeout: 30s` as a line of the command
 pub fn create_command_processor() -> SyncProcess {
     SyncProcess::new(
         "Command processor",
@@ -176,16 +304,30 @@ pub fn create_command_processor() -> SyncProcess {
         |event: &FileEvent| {
             Some(event.path.clone())
         },
-        |_event, content| {
-            let content_str = String::from_utf8_lossy(&content);
+        |event, content| {
+            let content_str = String::from_utf8_lossy(content);
 
             // Parse the command log
             let mut log = CommandLog::parse(&content_str);
 
+            if event.config.get_bool("command", "enabled") == Some(false) {
+                return Ok(log.render().into_bytes());
+            }
+
+            let working_dir = event.config
+                .get_str("command", "working_dir")
+                .and_then(|dir| Some(event.path.parent()?.join(dir)));
+
+            let timeout = event.config
+                .get_int("command", "timeout_secs")
+                .map(|secs| Duration::from_secs(secs.max(1) as u64))
+                .unwrap_or(DEFAULT_COMMAND_TIMEOUT);
+
             // Execute commands that don't have results yet
             for entry in &mut log.entries {
                 if entry.result.is_none() || entry.result.as_ref().map(|r| r.is_empty()).unwrap_or(false) {
-                    let result = execute_command(&entry.command);
+                    let timeout = entry_timeout(&entry.command).unwrap_or(timeout);
+                    let result = execute_command(&entry.command, working_dir.as_deref(), timeout);
                     entry.result = Some(result);
                 }
             }
@@ -264,4 +406,67 @@ mod tests {
         assert_eq!(log.entries[0].command, "echo test");
         assert_eq!(log.entries[0].result, None);
     }
+
+    #[test]
+    fn test_entry_timeout() {
+        assert_eq!(entry_timeout("# timeout: 30s\nsleep 100"), Some(Duration::from_secs(30)));
+        assert_eq!(entry_timeout("make test # lang\n#timeout: 2m"), Some(Duration::from_secs(120)));
+        assert_eq!(entry_timeout("# timeout: 500ms\nls"), Some(Duration::from_millis(500)));
+        assert_eq!(entry_timeout("# timeout: 10\nls"), Some(Duration::from_secs(10)));
+        assert_eq!(entry_timeout("# timeout: bald\nls"), None);
+        assert_eq!(entry_timeout("echo '# timeout: 5s'"), None);
+    }
+
+    #[test]
+    fn test_entry_timeout_zero_and_overflow() {
+        assert_eq!(entry_timeout("# timeout: 0s\nls"), None);
+        assert_eq!(entry_timeout("# timeout: 0ms\nls"), None);
+        assert_eq!(entry_timeout("# timeout: 9999999999999999h\nls"), None);
+        assert_eq!(entry_timeout("# timeout: 99999999999999999999s\nls"), None);
+        assert_eq!(entry_timeout("# timeout: 99999999999999999s\nls"), Some(Duration::from_secs(99999999999999999)));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_execute_command_huge_timeout() {
+        let timeout = entry_timeout("# timeout: 99999999999999999s").unwrap();
+        assert_eq!(execute_command("echo hallo", None, timeout), "hallo\n");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_execute_command() {
+        assert_eq!(execute_command("echo hallo; echo fehler >&2", None, DEFAULT_COMMAND_TIMEOUT), "hallo\nfehler\n");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_execute_command_timeout_kills_group() {
+        let marker = std::env::temp_dir().join(format!("mara_command_timeout_{}", std::process::id()));
+        let command = format!("echo start; (sleep 1; touch {}) & sleep 30", marker.display());
+
+        let started = Instant::now();
+        let result = execute_command(&command, None, Duration::from_millis(300));
+        assert!(started.elapsed() < Duration::from_secs(5));
+        assert_eq!(result, "start\ntimed out after 300ms");
+
+        // The background child was killed with the group
+        thread::sleep(Duration::from_millis(1500));
+        assert!(!marker.exists());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_execute_command_background_child_holding_output() {
+        let started = Instant::now();
+        let result = execute_command("echo start; sleep 30 &", None, Duration::from_millis(300));
+        assert!(started.elapsed() < Duration::from_secs(5));
+        assert_eq!(result, "start\ntimed out after 300ms");
+    }
+
+    #[test]
+    fn test_timed_out() {
+        assert_eq!(timed_out(Duration::from_secs(60)), "timed out after 60s");
+        assert_eq!(timed_out(Duration::from_millis(500)), "timed out after 500ms");
+    }
 }
@@ -1,4 +1,7 @@
 use crate::{FileEvent, EventOrigin, SyncProcess};
+use super::chat_commands::EXPORT_EXTENSION;
+use super::persona::Persona;
+use super::rag::Retriever;
 use std::fs;
 use std::path::{Path, PathBuf};
 
@@ -21,7 +24,7 @@ impl DokuEntry {
 }
 
 /// DokuIndex struct - contains documentation index
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Default)]
 pub struct DokuIndex {
     pub entries: Vec<DokuEntry>,
 }
@@ -78,10 +81,10 @@ impl DokuIndex {
                         break;
                     }
 
-                    if current.starts_with("**Path:**") {
-                        path = current[9..].trim().to_string();
-                    } else if current.starts_with("**Last Updated:**") {
-                        last_updated = current[17..].trim().to_string();
+                    if let Some(rest) = current.strip_prefix("**Path:**") {
+                        path = rest.trim().to_string();
+                    } else if let Some(rest) = current.strip_prefix("**Last Updated:**") {
+                        last_updated = rest.trim().to_string();
                     } else if current.starts_with("**Summary:**") {
                         in_summary = true;
                         i += 1;
@@ -109,8 +112,8 @@ impl DokuIndex {
         index
     }
 
-    /// Scan for markdown files in a directory
-    pub fn scan_markdown_files(root_path: &Path) -> Vec<(PathBuf, String)> {
+    /// Scan for markdown files in a directory, only documents (see `is_document`)
+    pub fn scan_markdown_files(root_path: &Path, personas_dir: &Path) -> Vec<(PathBuf, String)> {
         let mut files = Vec::new();
 
         if let Ok(entries) = fs::read_dir(root_path) {
@@ -118,16 +121,14 @@ impl DokuIndex {
                 let path = entry.path();
 
                 if path.is_file() {
-                    if let Some(ext) = path.extension() {
-                        if ext == "md" {
-                            if let Ok(content) = fs::read_to_string(&path) {
-                                files.push((path, content));
-                            }
+                    if is_document(&path, personas_dir) {
+                        if let Ok(content) = fs::read_to_string(&path) {
+                            files.push((path, content));
                         }
                     }
                 } else if path.is_dir() {
                     // Recursively scan subdirectories
-                    let mut subfiles = Self::scan_markdown_files(&path);
+                    let mut subfiles = Self::scan_markdown_files(&path, personas_dir);
                     files.append(&mut subfiles);
                 }
             }
@@ -204,7 +205,7 @@ impl DokuIndex {
         // Simple date approximation (not perfect but works)
         let year = 1970 + (days_since_epoch / 365) as u32;
         let day_of_year = (days_since_epoch % 365) as u32;
-        let month = (day_of_year / 30).min(12).max(1);
+        let month = (day_of_year / 30).clamp(1, 12);
         let day = (day_of_year % 30).max(1);
 
         output.push_str(&format!(
@@ -217,19 +218,30 @@ impl DokuIndex {
     }
 }
 
+/// Markdown files that are documentation: no generated index, no chats or chat exports
+/// and nothing in the persona directory, persona prompts aren't docs
+pub fn is_document(path: &Path, personas_dir: &Path) -> bool {
+    path.file_name()
+        .and_then(|n| n.to_str())
+        .map(|name| {
+            name.ends_with(".md")
+                && name != "index.md"
+                && !name.ends_with(".chat.md")
+                && !name.ends_with(EXPORT_EXTENSION)
+        })
+        .unwrap_or(false)
+        && !path.starts_with(personas_dir)
+}
+
 /// Doku processor - scans markdown files and creates documentation index
 /// Filter: .md files or .doku files
 /// Target: .doku file in the same directory
-/// Transform: scan markdown files, create index, render back
+/// Transform: scan markdown files, create index, render back, update the RAG index
 pub fn create_doku_processor() -> SyncProcess {
     SyncProcess::new(
         "Doku processor",
         |event: &FileEvent| {
-            let filename = event.path
-                .file_name()
-                .and_then(|n| n.to_str())
-                .map(|name| name.ends_with(".md") && !name.ends_with("index.md"))
-                .unwrap_or(false);
+            let filename = is_document(&event.path, &Persona::dir(&event.config, event.root.as_deref()));
 
             let right_origin = match &event.origin {
                 EventOrigin::External => true,
@@ -256,12 +268,20 @@ pub fn create_doku_processor() -> SyncProcess {
             };
 
             // Scan for all markdown files
-            let md_files = DokuIndex::scan_markdown_files(&dir);
+            let personas_dir = Persona::dir(&event.config, event.root.as_deref());
+            let md_files = DokuIndex::scan_markdown_files(&dir, &personas_dir);
 
             if md_files.is_empty() {
                 return Ok(Vec::new());
             }
 
+            // Keep the vector index for chat retrieval up to date, if [rag] is enabled
+            if let Some(retriever) = event.root.as_deref().and_then(|root| Retriever::from_config(&event.config, root)) {
+                if let Err(e) = retriever.index_files(&md_files) {
+                    eprintln!("[Doku processor] RAG error: {}", e);
+                }
+            }
+
             // Create entries for each markdown file
             let mut index = DokuIndex::new();
 
@@ -277,7 +297,7 @@ pub fn create_doku_processor() -> SyncProcess {
             let secs = seconds_today % 60;
             let year = 1970 + (days_since_epoch / 365) as u32;
             let day_of_year = (days_since_epoch % 365) as u32;
-            let month = (day_of_year / 30).min(12).max(1);
+            let month = (day_of_year / 30).clamp(1, 12);
             let day = (day_of_year % 30).max(1);
             let now = format!("{}-{:02}-{:02} {:02}:{:02}:{:02}", year, month, day, hours, minutes, secs);
 
@@ -306,6 +326,38 @@ pub fn create_doku_processor() -> SyncProcess {
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_is_document() {
+        let personas = Path::new("/w/personas");
+        assert!(is_document(Path::new("/w/notes/plan.md"), personas));
+        assert!(is_document(Path::new("/w/reindex.md"), personas));
+        assert!(!is_document(Path::new("/w/notes/index.md"), personas));
+        assert!(!is_document(Path::new("/w/plan.chat.md"), personas));
+        assert!(!is_document(Path::new("/w/plan.export.md"), personas));
+        assert!(!is_document(Path::new("/w/personas/mara.md"), personas));
+        assert!(!is_document(Path::new("/w/notes/plan.txt"), personas));
+    }
+
+    #[test]
+    fn test_scan_markdown_files() {
+        let dir = std::env::temp_dir().join(format!("mara_doku_scan_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("notizen")).unwrap();
+        fs::create_dir_all(dir.join("personas")).unwrap();
+        for file in ["plan.md", "notizen/idee.md", "index.md", "plan.chat.md", "plan.export.md", "personas/mara.md", "plan.chat"] {
+            fs::write(dir.join(file), "Inhalt").unwrap();
+        }
+
+        let mut files: Vec<PathBuf> = DokuIndex::scan_markdown_files(&dir, &dir.join("personas"))
+            .into_iter()
+            .map(|(path, _)| path.strip_prefix(&dir).unwrap().to_path_buf())
+            .collect();
+        files.sort();
+        assert_eq!(files, vec![PathBuf::from("notizen/idee.md"), PathBuf::from("plan.md")]);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
     #[test]
     fn test_create_summary_simple() {
         let content = "# Title\n\nThis is a test file with some content that should be summarized.";
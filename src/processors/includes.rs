@@ -0,0 +1,270 @@
+use crate::DirConfig;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// IncludeLimits struct - how much workspace content a single message may pull in
+#[derive(Debug, Clone, PartialEq)]
+pub struct IncludeLimits {
+    pub max_files: usize,
+    pub max_bytes_per_file: usize,
+    pub max_total_bytes: usize,
+}
+
+impl Default for IncludeLimits {
+    fn default() -> Self {
+        IncludeLimits {
+            max_files: 20,
+            max_bytes_per_file: 20_000,
+            max_total_bytes: 60_000,
+        }
+    }
+}
+
+impl IncludeLimits {
+    /// Read the limits from .mara.toml
+    /// [chat]
+    /// include_max_files = 20
+    /// include_max_bytes = 20000
+    /// include_max_total = 60000
+    pub fn from_config(config: &DirConfig) -> Self {
+        let defaults = IncludeLimits::default();
+        let get = |key: &str, default: usize| {
+            config
+                .get_int("chat", key)
+                .map(|v| v.max(0) as usize)
+                .unwrap_or(default)
+        };
+
+        IncludeLimits {
+            max_files: get("include_max_files", defaults.max_files),
+            max_bytes_per_file: get("include_max_bytes", defaults.max_bytes_per_file),
+            max_total_bytes: get("include_max_total", defaults.max_total_bytes),
+        }
+    }
+}
+
+/// Includes struct - expands `@file:` and `@glob:` directives of a message
+///
+/// Format (one directive per line):
+/// @file: src/lib/manager.rs
+/// @glob: docs/*.md
+///
+/// Paths are relative to the watch root, nothing outside of it can be included.
+/// Only the prompt gets the expanded content, the .chat file keeps the directive.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Includes {
+    root: PathBuf,
+    limits: IncludeLimits,
+}
+
+impl Includes {
+    pub fn new(root: &Path, limits: IncludeLimits) -> Self {
+        let root = fs::canonicalize(root).unwrap_or_else(|_| root.to_path_buf());
+        Includes { root, limits }
+    }
+
+    /// Canonical watch root all paths are relative to
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Read a single file below the root, cut to the per-file limit
+    pub fn read_file(&self, path: &str) -> Result<String, String> {
+        let mut text = self.read(path)?;
+        if text.len() > self.limits.max_bytes_per_file {
+            text.truncate(floor_char_boundary(&text, self.limits.max_bytes_per_file));
+            text.push_str("\n... (gekürzt)");
+        }
+        Ok(text)
+    }
+
+    fn directive(line: &str) -> Option<(&str, &str)> {
+        let line = line.trim();
+        if let Some(path) = line.strip_prefix("@file:") {
+            Some(("file", path.trim()))
+        } else if let Some(pattern) = line.strip_prefix("@glob:") {
+            Some(("glob", pattern.trim()))
+        } else {
+            None
+        }
+    }
+
+    /// Replace every directive line with the content of the files it names
+    pub fn expand(&self, content: &str) -> String {
+        let mut output = Vec::new();
+        let mut files = 0;
+        let mut total = 0;
+
+        for line in content.lines() {
+            let Some((kind, argument)) = Self::directive(line) else {
+                output.push(line.to_string());
+                continue;
+            };
+
+            let paths = match kind {
+                "file" => vec![argument.to_string()],
+                _ => match self.glob(argument) {
+                    Ok(paths) if paths.is_empty() => {
+                        output.push(format!("[Keine Dateien gefunden: {}]", argument));
+                        continue;
+                    }
+                    Ok(paths) => paths,
+                    Err(e) => {
+                        output.push(format!("[{}]", e));
+                        continue;
+                    }
+                },
+            };
+
+            for path in paths {
+                if files >= self.limits.max_files || total >= self.limits.max_total_bytes {
+                    output.push(format!("[Nicht eingefügt, Limit erreicht: {}]", path));
+                    continue;
+                }
+
+                match self.read(&path) {
+                    Ok(mut text) => {
+                        let limit = self
+                            .limits
+                            .max_bytes_per_file
+                            .min(self.limits.max_total_bytes - total);
+                        let truncated = text.len() > limit;
+                        if truncated {
+                            text.truncate(floor_char_boundary(&text, limit));
+                        }
+
+                        files += 1;
+                        total += text.len();
+
+                        output.push(format!("Datei `{}`:", path));
+                        output.push("```".to_string());
+                        output.push(text.trim_end().to_string());
+                        if truncated {
+                            output.push("... (gekürzt)".to_string());
+                        }
+                        output.push("```".to_string());
+                    }
+                    Err(e) => output.push(format!("[{}]", e)),
+                }
+            }
+        }
+
+        output.join("\n")
+    }
+
+    /// Resolve a path relative to the root, it has to stay inside of the root
+    fn resolve(&self, path: &str) -> Result<PathBuf, String> {
+        let resolved = fs::canonicalize(self.root.join(path))
+            .map_err(|_| format!("Datei nicht gefunden: {}", path))?;
+
+        if !resolved.starts_with(&self.root) {
+            return Err(format!("Datei liegt außerhalb des Workspace: {}", path));
+        }
+        Ok(resolved)
+    }
+
+    fn read(&self, path: &str) -> Result<String, String> {
+        let resolved = self.resolve(path)?;
+        if !resolved.is_file() {
+            return Err(format!("Keine Datei: {}", path));
+        }
+
+        let bytes = fs::read(&resolved).map_err(|e| format!("Lesefehler {}: {}", path, e))?;
+        Ok(String::from_utf8_lossy(&bytes).to_string())
+    }
+
+    /// Paths (relative to the root) of all files matching a pattern, sorted
+    fn glob(&self, pattern: &str) -> Result<Vec<String>, String> {
+        if Path::new(pattern).is_absolute() || pattern.split('/').any(|part| part == "..") {
+            return Err(format!("Muster liegt außerhalb des Workspace: {}", pattern));
+        }
+
+        let full_pattern = format!(
+            "{}/{}",
+            glob::Pattern::escape(&self.root.to_string_lossy()),
+            pattern
+        );
+        let entries = glob::glob(&full_pattern)
+            .map_err(|e| format!("Ungültiges Muster {}: {}", pattern, e))?;
+
+        let mut paths: Vec<String> = entries
+            .flatten()
+            .filter(|path| path.is_file())
+            .filter_map(|path| {
+                path.strip_prefix(&self.root)
+                    .ok()
+                    .map(|p| p.to_string_lossy().to_string())
+            })
+            .collect();
+        paths.sort();
+        Ok(paths)
+    }
+}
+
+/// Largest index <= `index` that is a char boundary
+fn floor_char_boundary(text: &str, index: usize) -> usize {
+    let mut index = index.min(text.len());
+    while !text.is_char_boundary(index) {
+        index -= 1;
+    }
+    index
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn workspace(name: &str) -> PathBuf {
+        let root = std::env::temp_dir().join(format!("mara_includes_{}_{}", name, std::process::id()));
+        fs::create_dir_all(root.join("docs")).unwrap();
+        fs::write(root.join("docs").join("a.md"), "Inhalt A").unwrap();
+        fs::write(root.join("docs").join("b.md"), "Inhalt B").unwrap();
+        fs::write(root.join("notes.txt"), "Notizen").unwrap();
+        root
+    }
+
+    #[test]
+    fn test_expand_file() {
+        let root = workspace("file");
+        let includes = Includes::new(&root, IncludeLimits::default());
+        let expanded = includes.expand("Was steht hier?\n@file: notes.txt");
+        assert_eq!(expanded, "Was steht hier?\nDatei `notes.txt`:\n```\nNotizen\n```");
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_expand_glob() {
+        let root = workspace("glob");
+        let includes = Includes::new(&root, IncludeLimits::default());
+        let expanded = includes.expand("@glob: docs/*.md");
+        assert!(expanded.contains("Datei `docs/a.md`:\n```\nInhalt A\n```"));
+        assert!(expanded.contains("Datei `docs/b.md`:\n```\nInhalt B\n```"));
+        assert!(expanded.find("docs/a.md").unwrap() < expanded.find("docs/b.md").unwrap());
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_outside_of_root() {
+        let root = workspace("outside");
+        let includes = Includes::new(&root, IncludeLimits::default());
+        assert!(includes.expand("@file: ../../etc/passwd").starts_with("[Datei"));
+        assert!(!includes.expand("@file: /etc/passwd").contains("root:"));
+        assert!(includes.expand("@glob: ../*").contains("außerhalb"));
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_limits() {
+        let root = workspace("limits");
+        let limits = IncludeLimits {
+            max_files: 1,
+            max_bytes_per_file: 5,
+            max_total_bytes: 100,
+        };
+        let includes = Includes::new(&root, limits);
+        let expanded = includes.expand("@glob: docs/*.md");
+        assert!(expanded.contains("Inhal\n... (gekürzt)"));
+        assert!(expanded.contains("[Nicht eingefügt, Limit erreicht: docs/b.md]"));
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -1,9 +1,15 @@
 pub mod sync_a_to_b;
 pub mod sync_a_to_c;
 pub mod chat_processor;
+pub mod chat_commands;
+pub mod chat_format;
 pub mod command_processor;
 pub mod todo_processor;
 pub mod doku_processor;
+pub mod persona;
+pub mod includes;
+pub mod rag;
+pub mod tools;
 
 pub use sync_a_to_b::create_sync_a_to_b;
 pub use sync_a_to_c::create_sync_a_to_c;
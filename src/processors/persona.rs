@@ -0,0 +1,309 @@
+use crate::{CompletionOptions, DirConfig, FrontMatter, LlmMessage, Role};
+use super::chat_format::chat_stem;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Persona used when a chat doesn't pick one
+pub const DEFAULT_PERSONA: &str = "mara";
+
+/// Directory of the persona files in the watch root if .mara.toml doesn't set `personas_dir`
+pub const DEFAULT_PERSONAS_DIR: &str = "personas";
+
+/// Persona of the rolling summary blocks that replace older turns in the prompt
+pub const SUMMARY_PERSONA: &str = "summary";
+
+/// Persona of the transcript blocks of tool calls
+pub const TOOL_PERSONA: &str = "tool";
+
+/// Persona of the error blocks written when a reply failed, they are never sent
+pub const ERROR_PERSONA: &str = "error";
+
+/// Persona of earlier replies replaced by `/retry`, they are kept but never sent
+pub const ALTERNATIVE_PERSONA: &str = "alternative";
+
+/// Persona struct - an AI participant defined by a persona file (e.g. personas/mara.md)
+///
+/// Format:
+/// ---
+/// model: gpt-4o
+/// temperature: 0.7
+/// ---
+/// <system prompt>
+#[derive(Debug, Clone, PartialEq)]
+pub struct Persona {
+    pub name: String,
+    pub system_prompt: Option<String>,
+    pub model: Option<String>,
+    pub temperature: Option<f32>,
+}
+
+impl Persona {
+    pub fn new(name: &str) -> Self {
+        Persona {
+            name: name.to_string(),
+            system_prompt: None,
+            model: None,
+            temperature: None,
+        }
+    }
+
+    /// Parse the content of a persona file
+    pub fn parse(name: &str, content: &str) -> Self {
+        let (front_matter, body) = FrontMatter::split(content);
+        let front_matter = front_matter.unwrap_or_default();
+
+        let system_prompt = body.trim();
+
+        Persona {
+            name: name.to_string(),
+            system_prompt: if system_prompt.is_empty() {
+                None
+            } else {
+                Some(system_prompt.to_string())
+            },
+            model: front_matter.get("model").map(|m| m.to_string()),
+            temperature: front_matter.get("temperature").and_then(|t| t.parse().ok()),
+        }
+    }
+
+    /// Directory of the persona files, relative to the watch root
+    /// [chat]
+    /// personas_dir = "personas"
+    pub fn dir(config: &DirConfig, root: Option<&Path>) -> PathBuf {
+        let dir = Path::new(config.get_str("chat", "personas_dir").unwrap_or(DEFAULT_PERSONAS_DIR));
+        match root {
+            Some(root) => root.join(dir),
+            None => dir.to_path_buf(),
+        }
+    }
+
+    /// A persona name is a plain file name, it can't leave the persona directory
+    pub fn is_valid_name(name: &str) -> bool {
+        !name.is_empty()
+            && !name.contains('/')
+            && !name.contains('\\')
+            && !name.contains("..")
+            && !name.starts_with('.')
+    }
+
+    /// Load `<name>.md` from the persona directory
+    pub fn load(dir: &Path, name: &str) -> Option<Self> {
+        if !Persona::is_valid_name(name) {
+            return None;
+        }
+        let content = fs::read_to_string(dir.join(format!("{}.md", name))).ok()?;
+        Some(Persona::parse(name, &content))
+    }
+
+    /// Find the persona of a chat
+    /// 1. header line of the chat (`persona: critic`)
+    /// 2. file name: `critic.chat` or `review.critic.chat`, if a persona file exists
+    /// 3. the default persona
+    ///
+    /// A header name that isn't a plain file name (`../notes/secret`) gets the default persona
+    pub fn resolve(dir: &Path, header: Option<&str>, chat_path: &Path) -> Self {
+        match header {
+            Some(name) if Persona::is_valid_name(name) => {
+                return Persona::load(dir, name).unwrap_or_else(|| Persona::new(name));
+            }
+            Some(name) => {
+                eprintln!("Ungültiger Persona-Name: {}", name);
+                return Persona::default_persona(dir);
+            }
+            None => {}
+        }
+
+        let stem = chat_stem(chat_path);
+        let candidates = [stem.rsplit('.').next().unwrap_or(&stem), stem.as_str()];
+
+        candidates
+            .iter()
+            .filter(|name| !name.is_empty())
+            .find_map(|name| Persona::load(dir, name))
+            .unwrap_or_else(|| Persona::default_persona(dir))
+    }
+
+    fn default_persona(dir: &Path) -> Self {
+        Persona::load(dir, DEFAULT_PERSONA).unwrap_or_else(|| Persona::new(DEFAULT_PERSONA))
+    }
+
+    /// Request options defined by this persona
+    pub fn options(&self) -> CompletionOptions {
+        CompletionOptions {
+            system: self.system_prompt.clone(),
+            model: self.model.clone(),
+            temperature: self.temperature,
+            max_tokens: None,
+        }
+    }
+}
+
+/// RoleMap struct - decides which API role the messages of a persona get
+/// - `system:`, `summary:` and `tool:` blocks are system messages
+/// - the replying persona and the declared assistant personas are the assistant
+/// - everybody else (User, Alice, Bob, ...) is a user, their name is passed along
+#[derive(Debug, Clone, PartialEq)]
+pub struct RoleMap {
+    pub user_personas: Vec<String>,
+    pub assistant_personas: Vec<String>,
+}
+
+impl RoleMap {
+    /// Role map where only the given persona is the assistant
+    pub fn new(assistant: &str) -> Self {
+        RoleMap {
+            user_personas: Vec::new(),
+            assistant_personas: vec![assistant.to_string()],
+        }
+    }
+
+    /// Make more personas assistants, e.g. all AI personas of a chat
+    pub fn with_assistants(mut self, personas: &[String]) -> Self {
+        for persona in personas {
+            if !self.assistant_personas.contains(persona) {
+                self.assistant_personas.push(persona.clone());
+            }
+        }
+        self
+    }
+
+    /// Add the personas declared in .mara.toml and in the chat's front matter
+    /// [chat]
+    /// users = ["Alice", "Bob"]
+    /// assistants = ["mara"]
+    ///
+    /// ---
+    /// users: Alice, Bob
+    /// assistants: mara
+    /// ---
+    pub fn with_declared(mut self, config: &DirConfig, front_matter: Option<&FrontMatter>) -> Self {
+        for (key, personas) in [("users", &mut self.user_personas), ("assistants", &mut self.assistant_personas)] {
+            if let Some(list) = config.get("chat", key).and_then(|v| v.as_array()) {
+                personas.extend(list.iter().filter_map(|v| v.as_str()).map(|p| p.to_string()));
+            }
+            if let Some(list) = front_matter.and_then(|f| f.get(key)) {
+                personas.extend(list.split(',').map(|p| p.trim().to_string()).filter(|p| !p.is_empty()));
+            }
+        }
+        self
+    }
+
+    pub fn role(&self, persona: &str) -> Role {
+        if persona.eq_ignore_ascii_case("system")
+            || [SUMMARY_PERSONA, TOOL_PERSONA, ERROR_PERSONA, ALTERNATIVE_PERSONA].contains(&persona)
+        {
+            Role::System
+        } else if self.user_personas.iter().any(|p| p == persona) {
+            Role::User
+        } else if self.assistant_personas.iter().any(|p| p == persona) {
+            Role::Assistant
+        } else {
+            Role::User
+        }
+    }
+
+    /// Create the provider message for a chat message
+    pub fn message(&self, persona: &str, content: &str) -> LlmMessage {
+        let role = self.role(persona);
+        let message = LlmMessage::new(role, content);
+
+        match role {
+            Role::System | Role::Tool => message,
+            Role::User | Role::Assistant => message.with_name(persona),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_persona() {
+        let persona = Persona::parse("mara", "---\nmodel: gpt-4o\ntemperature: 0.3\n---\nDu bist mara.\nAntworte auf Deutsch.\n");
+        assert_eq!(persona.name, "mara");
+        assert_eq!(persona.model, Some("gpt-4o".to_string()));
+        assert_eq!(persona.temperature, Some(0.3));
+        assert_eq!(persona.system_prompt, Some("Du bist mara.\nAntworte auf Deutsch.".to_string()));
+    }
+
+    #[test]
+    fn test_parse_prompt_only() {
+        let persona = Persona::parse("critic", "Du bist ein kritischer Reviewer.");
+        assert_eq!(persona.model, None);
+        assert_eq!(persona.temperature, None);
+        assert_eq!(persona.system_prompt, Some("Du bist ein kritischer Reviewer.".to_string()));
+    }
+
+    #[test]
+    fn test_resolve() {
+        let dir = std::env::temp_dir().join(format!("mara_persona_test_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("mara.md"), "Du bist mara.").unwrap();
+        fs::write(dir.join("critic.md"), "Du bist kritisch.").unwrap();
+
+        let persona = Persona::resolve(&dir, Some("critic"), Path::new("test.chat"));
+        assert_eq!(persona.system_prompt, Some("Du bist kritisch.".to_string()));
+
+        let persona = Persona::resolve(&dir, None, Path::new("review.critic.chat"));
+        assert_eq!(persona.name, "critic");
+
+        let persona = Persona::resolve(&dir, None, Path::new("beispiel.chat"));
+        assert_eq!(persona.name, "mara");
+        assert_eq!(persona.system_prompt, Some("Du bist mara.".to_string()));
+
+        let persona = Persona::resolve(&dir, Some("unbekannt"), Path::new("beispiel.chat"));
+        assert_eq!(persona, Persona::new("unbekannt"));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_resolve_rejects_traversal() {
+        let base = std::env::temp_dir().join(format!("mara_persona_traversal_{}", std::process::id()));
+        let dir = base.join("personas");
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("mara.md"), "Du bist mara.").unwrap();
+        fs::write(base.join("secret.md"), "Geheim").unwrap();
+
+        for name in ["../secret", "..\\secret", "/tmp/secret", ".versteckt", "a/../../secret"] {
+            let persona = Persona::resolve(&dir, Some(name), Path::new("test.chat"));
+            assert_eq!(persona.name, "mara", "{}", name);
+            assert_eq!(persona.system_prompt, Some("Du bist mara.".to_string()));
+        }
+        assert_eq!(Persona::load(&dir, "../secret"), None);
+        assert!(Persona::is_valid_name("critic"));
+
+        fs::remove_dir_all(&base).unwrap();
+    }
+
+    #[test]
+    fn test_role_map_defaults() {
+        let roles = RoleMap::new("mara");
+        assert_eq!(roles.role("User"), Role::User);
+        assert_eq!(roles.role("Alice"), Role::User);
+        assert_eq!(roles.role("mara"), Role::Assistant);
+        assert_eq!(roles.role("system"), Role::System);
+        assert_eq!(roles.role("summary"), Role::System);
+        assert_eq!(roles.role("tool"), Role::System);
+    }
+
+    #[test]
+    fn test_role_map_declared() {
+        let config = DirConfig::parse("[chat]\nassistants = [\"Bob\"]\n").unwrap();
+        let (front_matter, _) = FrontMatter::split("---\nusers: mara-old, Carol\nassistants: critic\n---\n");
+        let roles = RoleMap::new("mara").with_declared(&config, front_matter.as_ref());
+        assert_eq!(roles.role("Bob"), Role::Assistant);
+        assert_eq!(roles.role("critic"), Role::Assistant);
+        assert_eq!(roles.role("mara-old"), Role::User);
+        assert_eq!(roles.role("Alice"), Role::User);
+    }
+
+    #[test]
+    fn test_role_map_message_names() {
+        let roles = RoleMap::new("mara");
+        assert_eq!(roles.message("Alice", "Hallo"), LlmMessage::new(Role::User, "Hallo").with_name("Alice"));
+        assert_eq!(roles.message("mara", "Hi"), LlmMessage::new(Role::Assistant, "Hi").with_name("mara"));
+        assert_eq!(roles.message("system", "Sei kurz."), LlmMessage::new(Role::System, "Sei kurz."));
+    }
+}
@@ -0,0 +1,430 @@
+use crate::{DirConfig, EmbeddingProvider, OpenAIClient};
+use crate::lib::hash::fnv1a;
+use super::doku_processor::is_document;
+use super::persona::Persona;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Vector index in the watch root
+pub const RAG_INDEX_FILE: &str = ".mara-rag.json";
+
+/// Chunks added to the prompt if .mara.toml doesn't set `top_k`
+const DEFAULT_TOP_K: usize = 3;
+
+/// Longest chunk, paragraphs are joined up to this size
+const CHUNK_CHARS: usize = 1200;
+
+/// Size of the vectors of the LocalEmbedder
+const LOCAL_DIMENSIONS: usize = 256;
+
+/// Chunks below this similarity are never added to the prompt
+const MIN_SIMILARITY: f32 = 0.05;
+
+/// LocalEmbedder struct - deterministic bag-of-words vectors, for tests and offline use
+/// Words are hashed into the buckets of the vector, similar texts share words
+pub struct LocalEmbedder {
+    model: String,
+    dimensions: usize,
+}
+
+impl LocalEmbedder {
+    pub fn new(dimensions: usize) -> Self {
+        LocalEmbedder {
+            model: format!("local-{}", dimensions),
+            dimensions: dimensions.max(1),
+        }
+    }
+
+    /// Normalized vector of the words of `text`
+    pub fn vector(&self, text: &str) -> Vec<f32> {
+        let mut vector = vec![0.0; self.dimensions];
+        let lowercase = text.to_lowercase();
+        let words = lowercase
+            .split(|c: char| !c.is_alphanumeric())
+            .filter(|word| word.chars().count() > 1);
+        for word in words {
+            let hash = fnv1a(word.as_bytes());
+            let sign = if hash >> 63 == 0 { 1.0 } else { -1.0 };
+            vector[(hash % self.dimensions as u64) as usize] += sign;
+        }
+        normalize(vector)
+    }
+}
+
+impl EmbeddingProvider for LocalEmbedder {
+    fn embedding_model(&self) -> &str {
+        &self.model
+    }
+
+    async fn embed(&self, texts: Vec<String>) -> Result<Vec<Vec<f32>>, String> {
+        Ok(texts.iter().map(|text| self.vector(text)).collect())
+    }
+}
+
+/// Embedder enum - the embedding backend picked in .mara.toml
+pub enum Embedder {
+    Local(LocalEmbedder),
+    OpenAI(OpenAIClient),
+}
+
+impl Embedder {
+    /// [rag]
+    /// embeddings = "local"    # or "openai", uses the server of [chat]
+    /// model = "text-embedding-3-small"
+    pub fn from_config(config: &DirConfig) -> Result<Self, String> {
+        match config.get_str("rag", "embeddings").unwrap_or("openai") {
+            "local" => Ok(Embedder::Local(LocalEmbedder::new(LOCAL_DIMENSIONS))),
+            "openai" => {
+                let mut client = OpenAIClient::from_config(config)?;
+                if let Some(model) = config.get_str("rag", "model") {
+                    client = client.with_embedding_model(model);
+                }
+                Ok(Embedder::OpenAI(client))
+            }
+            other => Err(format!("Unbekannter Embedding-Anbieter: {}", other)),
+        }
+    }
+}
+
+impl EmbeddingProvider for Embedder {
+    fn embedding_model(&self) -> &str {
+        match self {
+            Embedder::Local(embedder) => embedder.embedding_model(),
+            Embedder::OpenAI(client) => client.embedding_model(),
+        }
+    }
+
+    async fn embed(&self, texts: Vec<String>) -> Result<Vec<Vec<f32>>, String> {
+        match self {
+            Embedder::Local(embedder) => embedder.embed(texts).await,
+            Embedder::OpenAI(client) => client.embed(texts).await,
+        }
+    }
+}
+
+/// Chunk struct - a part of a markdown file with its vector
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Chunk {
+    pub file: String,
+    pub text: String,
+    pub vector: Vec<f32>,
+}
+
+/// VectorIndex struct - chunks of the markdown files below the watch root
+/// Only files whose content changed are embedded again, a different model rebuilds the index
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub struct VectorIndex {
+    #[serde(skip)]
+    path: PathBuf,
+    pub model: String,
+    pub files: BTreeMap<String, u64>,   // Inhalts-Hash je Datei
+    pub chunks: Vec<Chunk>,
+}
+
+impl VectorIndex {
+    /// Load the index of a watch root, a missing or broken file starts empty
+    pub fn load(root: &Path) -> Self {
+        let path = root.join(RAG_INDEX_FILE);
+        let mut index: VectorIndex = fs::read_to_string(&path)
+            .ok()
+            .and_then(|content| serde_json::from_str(&content).ok())
+            .unwrap_or_default();
+        index.path = path;
+        index
+    }
+
+    pub fn save(&self) -> Result<(), String> {
+        let content = serde_json::to_string(self).map_err(|e| e.to_string())?;
+        fs::write(&self.path, content)
+            .map_err(|e| format!("Failed to write {}: {}", self.path.display(), e))
+    }
+
+    /// Embed the changed ones of `files` (relative path, content)
+    /// Returns whether the index changed
+    pub async fn update<E: EmbeddingProvider>(&mut self, embedder: &E, files: &[(String, String)]) -> Result<bool, String> {
+        let mut changed = false;
+        if self.model != embedder.embedding_model() {
+            changed = !self.chunks.is_empty();
+            self.model = embedder.embedding_model().to_string();
+            self.files.clear();
+            self.chunks.clear();
+        }
+
+        for (file, content) in files {
+            let hash = fnv1a(content.as_bytes());
+            if self.files.get(file) == Some(&hash) {
+                continue;
+            }
+
+            let texts = chunk_markdown(content, CHUNK_CHARS);
+            let vectors = embedder.embed(texts.clone()).await?;
+            self.remove(file);
+            self.chunks.extend(texts.into_iter().zip(vectors).map(|(text, vector)| Chunk {
+                file: file.clone(),
+                text,
+                vector,
+            }));
+            self.files.insert(file.clone(), hash);
+            changed = true;
+        }
+        Ok(changed)
+    }
+
+    /// Drop the chunks of a file
+    pub fn remove(&mut self, file: &str) -> bool {
+        self.chunks.retain(|chunk| chunk.file != file);
+        self.files.remove(file).is_some()
+    }
+
+    /// The `top_k` chunks most similar to the query vector, best first
+    pub fn search(&self, query: &[f32], top_k: usize) -> Vec<(&Chunk, f32)> {
+        let mut hits: Vec<(&Chunk, f32)> = self
+            .chunks
+            .iter()
+            .map(|chunk| (chunk, dot(&chunk.vector, query)))
+            .filter(|(_, similarity)| *similarity >= MIN_SIMILARITY)
+            .collect();
+        hits.sort_by(|a, b| b.1.total_cmp(&a.1));
+        hits.truncate(top_k);
+        hits
+    }
+}
+
+/// Retriever struct - keeps the index of a watch root up to date and finds chunks for a question
+///
+/// Off by default, enabled in the root's .mara.toml:
+/// [rag]
+/// enabled = true
+/// top_k = 3
+pub struct Retriever {
+    root: PathBuf,
+    personas_dir: PathBuf,
+    embedder: Embedder,
+    top_k: usize,
+}
+
+impl Retriever {
+    /// None if retrieval is disabled or the embedder can't be created
+    pub fn from_config(config: &DirConfig, root: &Path) -> Option<Self> {
+        if !config.get_bool("rag", "enabled").unwrap_or(false) {
+            return None;
+        }
+
+        let embedder = match Embedder::from_config(config) {
+            Ok(embedder) => embedder,
+            Err(e) => {
+                eprintln!("RAG error: {}", e);
+                return None;
+            }
+        };
+        Some(Retriever {
+            root: root.to_path_buf(),
+            personas_dir: Persona::dir(config, Some(root)),
+            embedder,
+            top_k: config.get_int("rag", "top_k").map(|k| k.max(1) as usize).unwrap_or(DEFAULT_TOP_K),
+        })
+    }
+
+    /// Update the index with the scanned markdown files, files that are gone are dropped
+    pub fn index_files(&self, files: &[(PathBuf, String)]) -> Result<(), String> {
+        let mut index = VectorIndex::load(&self.root);
+        let gone: Vec<String> = index
+            .files
+            .keys()
+            .filter(|file| !self.root.join(file).exists())
+            .cloned()
+            .collect();
+        let mut changed = false;
+        for file in gone {
+            changed |= index.remove(&file);
+        }
+
+        let files: Vec<(String, String)> = files
+            .iter()
+            .filter(|(path, _)| is_document(path, &self.personas_dir))
+            .filter_map(|(path, content)| {
+                let relative = path.strip_prefix(&self.root).ok()?;
+                Some((relative.to_string_lossy().to_string(), content.clone()))
+            })
+            .collect();
+
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        changed |= rt.block_on(index.update(&self.embedder, &files))?;
+        if changed {
+            index.save()?;
+        }
+        Ok(())
+    }
+
+    /// System message with the chunks that match the question, None if nothing matches
+    pub fn context(&self, question: &str) -> Result<Option<String>, String> {
+        let index = VectorIndex::load(&self.root);
+        if index.chunks.is_empty() || index.model != self.embedder.embedding_model() {
+            return Ok(None);
+        }
+
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        let query = rt
+            .block_on(self.embedder.embed(vec![question.to_string()]))?
+            .pop()
+            .ok_or("Keine Embedding-Antwort für die Frage")?;
+
+        let hits = index.search(&query, self.top_k);
+        if hits.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(context_message(&hits)))
+    }
+}
+
+/// Prompt block with the found chunks and their files
+pub fn context_message(hits: &[(&Chunk, f32)]) -> String {
+    let mut message = String::from(
+        "Relevante Ausschnitte aus der Dokumentation, nutze sie wenn sie zur Frage passen:",
+    );
+    for (chunk, _) in hits {
+        message.push_str(&format!("\n\n[{}]\n{}", chunk.file, chunk.text));
+    }
+    message
+}
+
+/// Split markdown into chunks of whole paragraphs, a heading starts a new chunk
+/// Paragraphs longer than `max_chars` are split on their own
+pub fn chunk_markdown(content: &str, max_chars: usize) -> Vec<String> {
+    let mut chunks = Vec::new();
+    let mut current = String::new();
+
+    for paragraph in content.split("\n\n").map(|p| p.trim()).filter(|p| !p.is_empty()) {
+        let heading = paragraph.starts_with('#');
+        if !current.is_empty() && (heading || current.len() + paragraph.len() + 2 > max_chars) {
+            chunks.push(std::mem::take(&mut current));
+        }
+
+        if paragraph.len() > max_chars {
+            let chars: Vec<char> = paragraph.chars().collect();
+            chunks.extend(chars.chunks(max_chars).map(|part| part.iter().collect::<String>()));
+            continue;
+        }
+
+        if !current.is_empty() {
+            current.push_str("\n\n");
+        }
+        current.push_str(paragraph);
+    }
+
+    if !current.is_empty() {
+        chunks.push(current);
+    }
+    chunks
+}
+
+
+fn normalize(mut vector: Vec<f32>) -> Vec<f32> {
+    let length = vector.iter().map(|x| x * x).sum::<f32>().sqrt();
+    if length > 0.0 {
+        vector.iter_mut().for_each(|x| *x /= length);
+    }
+    vector
+}
+
+/// Similarity of two vectors, the cosine for normalized ones
+fn dot(a: &[f32], b: &[f32]) -> f32 {
+    if a.len() != b.len() {
+        return 0.0;
+    }
+    a.iter().zip(b).map(|(x, y)| x * y).sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn docs() -> Vec<(String, String)> {
+        vec![
+            ("setup.md".to_string(), "# Installation\n\nDie Installation läuft mit cargo install mara_watch.".to_string()),
+            ("kochen.md".to_string(), "# Rezept\n\nPfannkuchen brauchen Mehl, Milch und Eier.".to_string()),
+        ]
+    }
+
+    #[test]
+    fn test_local_embedder_is_deterministic() {
+        let embedder = LocalEmbedder::new(64);
+        assert_eq!(embedder.vector("Hallo Welt"), embedder.vector("hallo, welt!"));
+        assert!((dot(&embedder.vector("Hallo Welt"), &embedder.vector("Hallo Welt")) - 1.0).abs() < 1e-5);
+        assert_eq!(embedder.vector(""), vec![0.0; 64]);
+    }
+
+    #[test]
+    fn test_chunk_markdown() {
+        let chunks = chunk_markdown("# A\n\neins\n\nzwei\n\n# B\n\ndrei", 100);
+        assert_eq!(chunks, vec!["# A\n\neins\n\nzwei", "# B\n\ndrei"]);
+
+        let chunks = chunk_markdown(&"x".repeat(25), 10);
+        assert_eq!(chunks.len(), 3);
+    }
+
+    #[test]
+    fn test_search() {
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        let embedder = LocalEmbedder::new(LOCAL_DIMENSIONS);
+        let mut index = VectorIndex::default();
+        assert!(rt.block_on(index.update(&embedder, &docs())).unwrap());
+        assert_eq!(index.chunks.len(), 2);
+
+        let query = embedder.vector("Wie funktioniert die Installation mit cargo?");
+        let hits = index.search(&query, 1);
+        assert_eq!(hits.len(), 1);
+        assert_eq!(hits[0].0.file, "setup.md");
+        assert!(context_message(&hits).contains("[setup.md]\n# Installation"));
+
+        assert!(index.search(&embedder.vector("Quantenphysik"), 3).is_empty());
+    }
+
+    #[test]
+    fn test_update_only_changed_files() {
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        let embedder = LocalEmbedder::new(LOCAL_DIMENSIONS);
+        let mut index = VectorIndex::default();
+        rt.block_on(index.update(&embedder, &docs())).unwrap();
+        assert!(!rt.block_on(index.update(&embedder, &docs())).unwrap());
+
+        let changed = vec![("kochen.md".to_string(), "Waffeln".to_string())];
+        assert!(rt.block_on(index.update(&embedder, &changed)).unwrap());
+        assert_eq!(index.chunks.iter().filter(|c| c.file == "kochen.md").count(), 1);
+
+        assert!(rt.block_on(index.update(&LocalEmbedder::new(32), &changed)).unwrap());
+        assert_eq!(index.model, "local-32");
+        assert_eq!(index.files.len(), 1);
+    }
+
+    #[test]
+    fn test_retriever() {
+        let root = std::env::temp_dir().join(format!("mara_rag_test_{}", std::process::id()));
+        fs::create_dir_all(root.join("docs")).unwrap();
+        let config = DirConfig::parse("[rag]\nenabled = true\nembeddings = \"local\"\ntop_k = 1\n").unwrap();
+        let retriever = Retriever::from_config(&config, &root).unwrap();
+
+        let files: Vec<(PathBuf, String)> = docs()
+            .into_iter()
+            .map(|(file, content)| (root.join("docs").join(file), content))
+            .chain([(root.join("docs/index.md"), "# Installation Index".to_string())])
+            .collect();
+        for (path, content) in &files {
+            fs::write(path, content).unwrap();
+        }
+        retriever.index_files(&files).unwrap();
+
+        let context = retriever.context("Installation mit cargo").unwrap().unwrap();
+        assert!(context.contains("[docs/setup.md]"));
+        assert!(!context.contains("index.md"));
+
+        fs::remove_file(root.join("docs/setup.md")).unwrap();
+        retriever.index_files(&files[1..2]).unwrap();
+        assert_eq!(retriever.context("Installation mit cargo").unwrap(), None);
+
+        assert!(Retriever::from_config(&DirConfig::default(), &root).is_none());
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -3,15 +3,14 @@ use crate::FileEvent;
 use crate::SyncProcess;
 
 /// Unidirectional sync A -> B
-/// Filter: .txt files from _mara/a only (prevent loops)
-/// Target: _mara/b/
+/// Filter: .txt files from a/ in the watch root only (prevent loops)
+/// Target: b/ in the watch root
 /// Transform: identity (no change)
 pub fn create_sync_a_to_b() -> SyncProcess {
     SyncProcess::new(
         "A->B (txt files)",
         |event: &FileEvent| {
-            let path_str = event.path.to_string_lossy();
-            let is_from_a = path_str.contains("_mara/a");
+            let is_from_a = event.relative_path.starts_with("a");
             let is_txt = event.path
                 .file_name()
                 .and_then(|n| n.to_str())
@@ -21,7 +20,7 @@ pub fn create_sync_a_to_b() -> SyncProcess {
         },
         |event: &FileEvent| {
             let filename = event.path.file_name()?.to_str()?.to_string();
-            Some(PathBuf::from("_mara/b").join(filename))
+            Some(PathBuf::from("b").join(filename))
         },
         |_event, content| Ok(content.to_vec()),
     )
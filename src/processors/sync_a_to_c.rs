@@ -3,15 +3,14 @@ use crate::{FileEvent, EventOrigin, SyncProcess};
 
 /// Bidirectional sync A <-> C
 /// Filter: Only external events (ignore events from internal syncs)
-/// Target: opposite directory
+/// Target: opposite directory (a/ <-> c/ in the watch root)
 /// Transform: identity
 pub fn create_sync_a_to_c() -> SyncProcess {
     SyncProcess::new(
         "A<->C (bidirectional)",
         |event: &FileEvent| {
             // Only process external events - ignore internal ones!
-            let path_str = event.path.to_string_lossy();
-            let right_path = path_str.contains("_mara/a") || path_str.contains("_mara/c");
+            let right_path = event.relative_path.starts_with("a") || event.relative_path.starts_with("c");
 
             let right_origin = match &event.origin {
                 EventOrigin::External => true,
@@ -23,13 +22,12 @@ pub fn create_sync_a_to_c() -> SyncProcess {
             right_path && right_origin
         },
         |event: &FileEvent| {
-            let path_str = event.path.to_string_lossy();
             let filename = event.path.file_name()?.to_str()?.to_string();
 
-            if path_str.contains("_mara/a") {
-                Some(PathBuf::from("_mara/c").join(filename))
-            } else if path_str.contains("_mara/c") {
-                Some(PathBuf::from("_mara/a").join(filename))
+            if event.relative_path.starts_with("a") {
+                Some(PathBuf::from("c").join(filename))
+            } else if event.relative_path.starts_with("c") {
+                Some(PathBuf::from("a").join(filename))
             } else {
                 None
             }
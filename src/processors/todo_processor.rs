@@ -24,7 +24,7 @@ impl TodoEntry {
 }
 
 /// TodoLog struct - contains a list of todo entries
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Default)]
 pub struct TodoLog {
     pub entries: Vec<TodoEntry>,
 }
@@ -184,7 +184,7 @@ pub fn create_todo_processor() -> SyncProcess {
             Some(event.path.clone())
         },
         |_event, content| {
-            let content_str = String::from_utf8_lossy(&content);
+            let content_str = String::from_utf8_lossy(content);
 
             // Parse the todo log
             let log = TodoLog::parse(&content_str);
@@ -197,6 +197,7 @@ pub fn create_todo_processor() -> SyncProcess {
 }
 
 #[cfg(test)]
+#[allow(clippy::bool_assert_comparison)]
 mod tests {
     use super::*;
 
@@ -0,0 +1,268 @@
+use super::command_processor::{CommandEntry, CommandLog};
+use super::includes::Includes;
+use super::todo_processor::{TodoEntry, TodoLog};
+use crate::{ToolCall, ToolSpec};
+use serde_json::{json, Value};
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+/// Result written into a .command file for suggested commands, so the command
+/// processor doesn't run them. Removing the line runs the command.
+pub const SUGGESTION_NOTE: &str = "[Vorschlag von mara - diese Zeile löschen und speichern, um den Befehl auszuführen]";
+
+pub type ToolFn = fn(&Includes, &Value) -> Result<String, String>;
+
+/// Tool struct - a function the model may call
+pub struct Tool {
+    pub name: &'static str,
+    pub description: &'static str,
+    pub parameters: fn() -> Value,
+    pub run: ToolFn,
+}
+
+/// ToolRegistry struct - tools offered to the model in a chat
+/// All tools work on paths relative to the watch root and can't leave it
+pub struct ToolRegistry {
+    pub tools: Vec<Tool>,
+}
+
+impl ToolRegistry {
+    pub fn new() -> Self {
+        ToolRegistry { tools: Vec::new() }
+    }
+
+    pub fn register(mut self, tool: Tool) -> Self {
+        self.tools.push(tool);
+        self
+    }
+
+    /// Registry with the workspace tools: read_file, add_todo, suggest_command
+    pub fn workspace() -> Self {
+        ToolRegistry::new()
+            .register(Tool {
+                name: "read_file",
+                description: "Liest eine Datei aus dem Workspace.",
+                parameters: || json!({
+                    "type": "object",
+                    "properties": {
+                        "path": { "type": "string", "description": "Pfad relativ zum Workspace" }
+                    },
+                    "required": ["path"]
+                }),
+                run: read_file,
+            })
+            .register(Tool {
+                name: "add_todo",
+                description: "Fügt einer .todo Datei ein neues Todo hinzu.",
+                parameters: || json!({
+                    "type": "object",
+                    "properties": {
+                        "file": { "type": "string", "description": "Pfad der .todo Datei relativ zum Workspace" },
+                        "text": { "type": "string", "description": "Text des Todos" }
+                    },
+                    "required": ["file", "text"]
+                }),
+                run: add_todo,
+            })
+            .register(Tool {
+                name: "suggest_command",
+                description: "Schlägt einen Shell-Befehl in einer .command Datei vor. Der Nutzer entscheidet, ob er ausgeführt wird.",
+                parameters: || json!({
+                    "type": "object",
+                    "properties": {
+                        "file": { "type": "string", "description": "Pfad der .command Datei relativ zum Workspace" },
+                        "command": { "type": "string", "description": "Der vorgeschlagene Befehl" }
+                    },
+                    "required": ["file", "command"]
+                }),
+                run: suggest_command,
+            })
+    }
+
+    /// Specs of all tools for the provider
+    pub fn specs(&self) -> Vec<ToolSpec> {
+        self.tools
+            .iter()
+            .map(|tool| ToolSpec {
+                name: tool.name.to_string(),
+                description: tool.description.to_string(),
+                parameters: (tool.parameters)(),
+            })
+            .collect()
+    }
+
+    /// Execute a tool call, errors are returned as result text so the model can react
+    pub fn call(&self, includes: &Includes, call: &ToolCall) -> String {
+        let Some(tool) = self.tools.iter().find(|tool| tool.name == call.name) else {
+            return format!("Fehler: Unbekanntes Tool {}", call.name);
+        };
+
+        let arguments: Value = match serde_json::from_str(&call.arguments) {
+            Ok(arguments) => arguments,
+            Err(e) => return format!("Fehler: Ungültige Argumente: {}", e),
+        };
+
+        match (tool.run)(includes, &arguments) {
+            Ok(result) => result,
+            Err(e) => format!("Fehler: {}", e),
+        }
+    }
+}
+
+impl Default for ToolRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn string_argument<'a>(arguments: &'a Value, name: &str) -> Result<&'a str, String> {
+    arguments
+        .get(name)
+        .and_then(|v| v.as_str())
+        .ok_or_else(|| format!("Argument fehlt: {}", name))
+}
+
+/// Resolve a file that may not exist yet, it has to stay inside of the root
+fn resolve_for_write(root: &Path, path: &str, extension: &str) -> Result<PathBuf, String> {
+    let relative = Path::new(path);
+    if !relative.components().all(|c| matches!(c, Component::Normal(_))) {
+        return Err(format!("Pfad liegt außerhalb des Workspace: {}", path));
+    }
+    if relative.extension().and_then(|e| e.to_str()) != Some(extension) {
+        return Err(format!("Keine .{} Datei: {}", extension, path));
+    }
+
+    let resolved = root.join(relative);
+    let parent = resolved
+        .parent()
+        .and_then(|p| fs::canonicalize(p).ok())
+        .ok_or_else(|| format!("Verzeichnis nicht gefunden: {}", path))?;
+    if !parent.starts_with(root) {
+        return Err(format!("Pfad liegt außerhalb des Workspace: {}", path));
+    }
+
+    Ok(resolved)
+}
+
+fn read_file(includes: &Includes, arguments: &Value) -> Result<String, String> {
+    includes.read_file(string_argument(arguments, "path")?)
+}
+
+fn add_todo(includes: &Includes, arguments: &Value) -> Result<String, String> {
+    let file = string_argument(arguments, "file")?;
+    let text = string_argument(arguments, "text")?.trim();
+    if text.is_empty() {
+        return Err("Leeres Todo".to_string());
+    }
+
+    let path = resolve_for_write(includes.root(), file, "todo")?;
+    let content = fs::read_to_string(&path).unwrap_or_default();
+
+    let mut log = TodoLog::parse(&content);
+    log.entries.insert(0, TodoEntry::new(text.to_string()));
+    fs::write(&path, log.render()).map_err(|e| format!("Schreibfehler {}: {}", file, e))?;
+
+    Ok(format!("Todo \"{}\" zu {} hinzugefügt", text, file))
+}
+
+fn suggest_command(includes: &Includes, arguments: &Value) -> Result<String, String> {
+    let file = string_argument(arguments, "file")?;
+    let command = string_argument(arguments, "command")?.trim();
+    if command.is_empty() {
+        return Err("Leerer Befehl".to_string());
+    }
+
+    let path = resolve_for_write(includes.root(), file, "command")?;
+    let content = fs::read_to_string(&path).unwrap_or_default();
+
+    let mut log = CommandLog::parse(&content);
+    log.add_entry(CommandEntry::with_result(command.to_string(), SUGGESTION_NOTE.to_string()));
+    fs::write(&path, log.render()).map_err(|e| format!("Schreibfehler {}: {}", file, e))?;
+
+    Ok(format!("Befehl \"{}\" in {} vorgeschlagen", command, file))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::includes::IncludeLimits;
+
+    fn workspace(name: &str) -> (PathBuf, Includes) {
+        let root = std::env::temp_dir().join(format!("mara_tools_{}_{}", name, std::process::id()));
+        fs::create_dir_all(&root).unwrap();
+        let includes = Includes::new(&root, IncludeLimits::default());
+        (includes.root().to_path_buf(), includes)
+    }
+
+    fn call(name: &str, arguments: Value) -> ToolCall {
+        ToolCall {
+            id: "call_1".to_string(),
+            name: name.to_string(),
+            arguments: arguments.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_specs() {
+        let specs = ToolRegistry::workspace().specs();
+        let names: Vec<&str> = specs.iter().map(|s| s.name.as_str()).collect();
+        assert_eq!(names, vec!["read_file", "add_todo", "suggest_command"]);
+        assert_eq!(specs[1].parameters["required"], json!(["file", "text"]));
+    }
+
+    #[test]
+    fn test_read_file() {
+        let (root, includes) = workspace("read");
+        fs::write(root.join("notes.txt"), "Notizen").unwrap();
+        let registry = ToolRegistry::workspace();
+        assert_eq!(registry.call(&includes, &call("read_file", json!({"path": "notes.txt"}))), "Notizen");
+        assert!(registry.call(&includes, &call("read_file", json!({"path": "../x"}))).starts_with("Fehler"));
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_add_todo() {
+        let (root, includes) = workspace("todo");
+        fs::write(root.join("haus.todo"), "Neues Todo:\n\nTodos:\n[] Rasen mähen\n").unwrap();
+        let registry = ToolRegistry::workspace();
+        let result = registry.call(&includes, &call("add_todo", json!({"file": "haus.todo", "text": "Müll rausbringen"})));
+        assert_eq!(result, "Todo \"Müll rausbringen\" zu haus.todo hinzugefügt");
+
+        let log = TodoLog::parse(&fs::read_to_string(root.join("haus.todo")).unwrap());
+        assert_eq!(log.entries.len(), 2);
+        assert_eq!(log.entries[0].text, "Müll rausbringen");
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_suggest_command_is_not_executed() {
+        let (root, includes) = workspace("command");
+        let registry = ToolRegistry::workspace();
+        registry.call(&includes, &call("suggest_command", json!({"file": "neu.command", "command": "ls -la"})));
+
+        let log = CommandLog::parse(&fs::read_to_string(root.join("neu.command")).unwrap());
+        assert_eq!(log.entries.len(), 1);
+        assert_eq!(log.entries[0].command, "ls -la");
+        assert_eq!(log.entries[0].result, Some(SUGGESTION_NOTE.to_string()));
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_write_outside_of_root() {
+        let (root, includes) = workspace("outside");
+        let registry = ToolRegistry::workspace();
+        let result = registry.call(&includes, &call("add_todo", json!({"file": "../evil.todo", "text": "x"})));
+        assert!(result.starts_with("Fehler: Pfad liegt außerhalb"));
+        let result = registry.call(&includes, &call("add_todo", json!({"file": "notes.txt", "text": "x"})));
+        assert!(result.starts_with("Fehler: Keine .todo Datei"));
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_unknown_tool() {
+        let (root, includes) = workspace("unknown");
+        let result = ToolRegistry::workspace().call(&includes, &call("rm", json!({})));
+        assert_eq!(result, "Fehler: Unbekanntes Tool rm");
+        fs::remove_dir_all(&root).unwrap();
+    }
+}